@@ -0,0 +1,265 @@
+use std::{
+    io::{Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use zip::{ZipArchive, read::ZipFile, result::ZipError};
+
+/// Opens an archive entry for reading, decrypting it with `password` if it's encrypted
+pub fn open_entry<'a, R: Read + Seek>(
+    zip: &'a mut ZipArchive<R>,
+    index: usize,
+    password: Option<&str>,
+) -> anyhow::Result<ZipFile<'a>> {
+    let result = match password {
+        Some(password) => zip.by_index_decrypt(index, password.as_bytes()),
+        None => zip.by_index(index),
+    };
+    result.map_err(|err| match err {
+        ZipError::InvalidPassword => anyhow!("Wrong archive password!"),
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => {
+            anyhow!("Archive is password protected, use --password to import it")
+        }
+        err => anyhow!("Failed to read archive entry: {}", err),
+    })
+}
+
+/// Longest file name most file systems allow, in bytes
+const MAX_NAME_LEN: usize = 255;
+
+/// Device names Windows doesn't allow as file names, even with an extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Whether Windows treats `name` as a device, e.g. `CON`, `nul.txt` or `COM1 .png`
+pub fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    RESERVED_NAMES.iter().any(|x| x.eq_ignore_ascii_case(stem))
+}
+
+/// Whether a path component would be read as something else than a file name on Windows:
+/// a drive letter (`C:`) or an alternate data stream (`file.osu::$DATA`)
+///
+/// Other colons are fine on unix, on Windows every colon names a stream
+fn is_windows_special(part: &str) -> bool {
+    let bytes = part.as_bytes();
+    let drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    drive || part.contains(":$") || (cfg!(windows) && part.contains(':'))
+}
+
+/// Path an entry named `name` is extracted to, relative to the map directory,
+/// `None` if the name could point anywhere outside of it
+///
+/// Every archive path goes through this before touching the file system:
+/// - backslashes used by some Windows-made archives are separators, not part of a file name
+/// - absolute paths, Windows drive letters (`C:`) and UNC prefixes are rejected
+/// - `..` may only climb back out of directories the name entered itself
+/// - NUL bytes would silently cut the path short in OS calls and are rejected
+/// - alternate data streams (`file.osu::$DATA`) and device names (`CON`, `NUL`...) are
+///   rejected, and so are names longer than file systems allow
+pub fn entry_path(name: &str) -> Option<PathBuf> {
+    if name.contains('\0') {
+        return None;
+    }
+    let normalized = name.replace('\\', "/");
+    let mut result = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => {
+                let text = part.to_string_lossy();
+                // Drive letters are plain components on unix, but not on Windows
+                if is_windows_special(&text) || is_reserved_name(&text) || text.len() > MAX_NAME_LEN
+                {
+                    return None;
+                }
+                result.push(part);
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !result.pop() {
+                    return None;
+                }
+            }
+            Component::Prefix(_) | Component::RootDir => return None,
+        }
+    }
+    if result.as_os_str().is_empty() {
+        return None;
+    }
+    Some(result)
+}
+
+/// Whether the archive contains at least one regular file, not just directories
+pub fn has_files<R: Read + Seek>(zip: &mut ZipArchive<R>) -> bool {
+    (0..zip.len()).any(|i| zip.by_index_raw(i).is_ok_and(|x| !x.is_dir()))
+}
+
+/// Checks that encrypted archives can be read with `password`,
+/// so imports fail before touching the map directory
+pub fn check_encryption<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    password: Option<&str>,
+) -> anyhow::Result<()> {
+    for i in 0..zip.len() {
+        if zip.by_index_raw(i)?.encrypted() {
+            // Opening an encrypted entry verifies the password
+            open_entry(zip, i, password)?;
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Whether `path` stays inside the map directory when joined to it
+    fn stays_inside(path: &Path) -> bool {
+        let map = Path::new("/repos/map");
+        let joined = map.join(path);
+        path.components().all(|x| matches!(x, Component::Normal(_)))
+            && joined.starts_with(map)
+            && joined != map
+    }
+
+    #[test]
+    fn keeps_regular_paths() {
+        assert_eq!(entry_path("a.osu"), Some(PathBuf::from("a.osu")));
+        assert_eq!(
+            entry_path("sb\\star.png"),
+            Some(PathBuf::from("sb/star.png"))
+        );
+        assert_eq!(entry_path("./sb/../bg.jpg"), Some(PathBuf::from("bg.jpg")));
+        assert_eq!(
+            entry_path("console.png"),
+            Some(PathBuf::from("console.png"))
+        );
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn keeps_colons_in_names() {
+        assert_eq!(
+            entry_path("Re:Zero.png"),
+            Some(PathBuf::from("Re:Zero.png"))
+        );
+        assert_eq!(
+            entry_path("sb/10:00.png"),
+            Some(PathBuf::from("sb/10:00.png"))
+        );
+    }
+
+    #[test]
+    fn rejects_escaping_paths() {
+        for name in [
+            "../a.osu",
+            "sb/../../a.osu",
+            "..\\a.osu",
+            "/etc/passwd",
+            "\\\\server\\share\\a.osu",
+            "C:\\Windows\\a.osu",
+            "c:a.osu",
+            "a.osu\0.png",
+            "",
+            ".",
+            "sb/..",
+        ] {
+            assert_eq!(entry_path(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_windows_specials() {
+        for name in [
+            "a.osu::$DATA",
+            "sb/a.png:stream:$DATA",
+            "CON",
+            "nul.txt",
+            "sb/COM1.png",
+            "LPT9 .wav",
+        ] {
+            assert_eq!(entry_path(name), None, "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_overlong_names() {
+        assert_eq!(entry_path(&"a".repeat(MAX_NAME_LEN + 1)), None);
+        let longest = "a".repeat(MAX_NAME_LEN);
+        assert_eq!(entry_path(&longest), Some(PathBuf::from(longest)));
+    }
+
+    /// Archive with `entries` built in memory, names ending in `/` are directories
+    fn archive(entries: &[&str]) -> ZipArchive<std::io::Cursor<Vec<u8>>> {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default();
+        for entry in entries {
+            match entry.strip_suffix('/') {
+                Some(dir) => writer.add_directory(dir, options).unwrap(),
+                None => {
+                    writer.start_file(*entry, options).unwrap();
+                    writer.write_all(b"data").unwrap();
+                }
+            }
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn directories_only_have_no_files() {
+        assert!(!has_files(&mut archive(&[])));
+        assert!(!has_files(&mut archive(&["sb/", "sb/extra/"])));
+        assert!(has_files(&mut archive(&["sb/", "sb/star.png"])));
+        assert!(has_files(&mut archive(&["a.osu"])));
+    }
+
+    proptest! {
+        #[test]
+        fn never_escapes(name in "\\PC*") {
+            if let Some(path) = entry_path(&name) {
+                prop_assert!(stays_inside(&path), "{:?} -> {:?}", name, path);
+            }
+        }
+
+        #[test]
+        fn never_escapes_path_like(
+            parts in prop::collection::vec(
+                prop_oneof![
+                    Just("..".to_string()),
+                    Just(".".to_string()),
+                    Just("".to_string()),
+                    Just("C:".to_string()),
+                    Just("\0".to_string()),
+                    Just("CON".to_string()),
+                    "[a-zA-Z0-9 .:$]{1,8}",
+                    "a{250,260}",
+                ],
+                0..8,
+            ),
+            separators in prop::collection::vec(prop_oneof![Just('/'), Just('\\')], 8),
+        ) {
+            let mut name = String::new();
+            for (i, part) in parts.iter().enumerate() {
+                if i > 0 {
+                    name.push(separators[i]);
+                }
+                name += part;
+            }
+            if let Some(path) = entry_path(&name) {
+                prop_assert!(stays_inside(&path), "{:?} -> {:?}", name, path);
+                prop_assert!(!path.to_string_lossy().contains('\0'));
+                for part in path.components() {
+                    let part = part.as_os_str().to_string_lossy();
+                    prop_assert!(part.len() <= MAX_NAME_LEN);
+                    prop_assert!(!is_reserved_name(&part));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use owo_colors::OwoColorize;
+use serde_json::{Value, json};
+
+use crate::{
+    Config, metadata,
+    repos::{self, OutputFormat},
+    sanitize_name,
+};
+
+/// Checks that every repository is named like an import of its committed map would name it,
+/// failing if any of them isn't
+///
+/// Mismatches usually come from maps whose metadata changed after the repository was
+/// created, or from repositories created before a change to the naming
+pub fn run(config: &Config, format: OutputFormat) -> anyhow::Result<()> {
+    let table = format == OutputFormat::Table;
+    let managed = repos::scan(config)?;
+    let (mut mismatched, mut failed, mut rows) = (0, 0, vec![]);
+    for repo in &managed {
+        let expected = repo
+            .open(config)
+            .and_then(|git| repos::committed_difficulties(&git, "HEAD", &repo.map_prefix(config)));
+        let expected = match expected {
+            Ok(difficulties) => metadata::osz_name(&difficulties, &config.metadata_from)
+                .map(|x| sanitize_name(&x, &config.name_substitute)),
+            Err(err) => {
+                failed += 1;
+                match table {
+                    true => println!("[{}] {}: {}", "x".red(), repo.name, err),
+                    false => rows.push(vec![
+                        json!(repo.name),
+                        json!("error"),
+                        Value::Null,
+                        json!(err.to_string()),
+                    ]),
+                }
+                continue;
+            }
+        };
+        let status = match &expected {
+            Some(expected) if *expected != repo.name => {
+                mismatched += 1;
+                if table {
+                    println!(
+                        "[{}] {} should be named {}",
+                        "!".yellow(),
+                        repo.name,
+                        expected.yellow()
+                    );
+                }
+                "mismatch"
+            }
+            Some(_) => {
+                if table {
+                    println!("[{}] {}", "+".green(), repo.name);
+                }
+                "ok"
+            }
+            None => {
+                if table {
+                    println!(
+                        "[{}] {}: {}",
+                        "?".dimmed(),
+                        repo.name,
+                        "no title or creator to name it after".dimmed()
+                    );
+                }
+                "unnamed"
+            }
+        };
+        if !table {
+            rows.push(vec![
+                json!(repo.name),
+                json!(status),
+                json!(expected),
+                Value::Null,
+            ]);
+        }
+    }
+    if !table {
+        repos::print_rows(
+            format,
+            &["repository", "status", "expected", "error"],
+            &rows,
+        );
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} repositories couldn't be checked", failed);
+    }
+    if mismatched > 0 {
+        anyhow::bail!(
+            "{} of {} repositories are named differently than their map, \
+             rename their directories to fix them",
+            mismatched,
+            managed.len()
+        );
+    }
+    if table {
+        println!("All repositories are named after their maps!");
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use std::ffi::OsStr;
+
+use clap::{ArgMatches, CommandFactory, parser::ValueSource};
+use owo_colors::OwoColorize;
+
+use crate::{Args, Config, detect};
+
+/// Prints every global option with the value in effect and where it came from
+pub fn run(config: &Config, args: &Args, matches: &ArgMatches) -> anyhow::Result<()> {
+    let command = Args::command();
+    let options: Vec<_> = command
+        .get_arguments()
+        .filter(|x| !x.is_positional() && x.get_id() != "help" && x.get_id() != "version")
+        .collect();
+    let width = options
+        .iter()
+        .filter_map(|x| x.get_long())
+        .map(|x| x.len() + 2)
+        .max()
+        .unwrap_or_default();
+
+    for option in options {
+        let id = option.get_id().as_str();
+        let Some(long) = option.get_long() else {
+            continue;
+        };
+        let source = match matches.value_source(id) {
+            Some(ValueSource::CommandLine) => "command line".to_string(),
+            Some(ValueSource::EnvVariable) => match option.get_env() {
+                Some(env) => format!("environment ({})", env.to_string_lossy()),
+                None => "environment".to_string(),
+            },
+            Some(ValueSource::DefaultValue) => "default".to_string(),
+            _ => "not set".to_string(),
+        };
+        let given = matches.value_source(id).is_some();
+        // Directories are shown as resolved, gitosu works them out itself when they aren't given
+        let (value, source) = match id {
+            "exports" => {
+                let source = match given {
+                    true => source,
+                    false
+                        if args.auto_detect
+                            && detect::exports_dir().is_some_and(|x| x == config.exports) =>
+                    {
+                        "auto-detected".to_string()
+                    }
+                    false => "current directory".to_string(),
+                };
+                (config.exports.to_string_lossy().to_string(), source)
+            }
+            "repositories" => {
+                let source = match given {
+                    true => source,
+                    false => "current directory".to_string(),
+                };
+                (config.repos.to_string_lossy().to_string(), source)
+            }
+            "temp_dir" => {
+                let source = match given {
+                    true => source,
+                    false => "default".to_string(),
+                };
+                (config.temp_dir.to_string_lossy().to_string(), source)
+            }
+            _ if option.is_hide_env_values_set() && matches.contains_id(id) => {
+                ("<hidden>".to_string(), source)
+            }
+            _ => match matches.get_raw(id) {
+                Some(values) => (
+                    values
+                        .map(OsStr::to_string_lossy)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    source,
+                ),
+                None => (String::new(), source),
+            },
+        };
+        println!(
+            "{:width$}  {}  {}",
+            format!("--{}", long).bold(),
+            value,
+            format!("({})", source).dimmed(),
+            width = width
+        );
+    }
+    Ok(())
+}
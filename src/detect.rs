@@ -0,0 +1,90 @@
+use std::path::PathBuf;
+
+use log::debug;
+
+/// Finds the `Exports` directory of an osu! (stable) installation, or the `exports`
+/// directory of osu!lazer if there is no stable one. `None` if neither was found
+pub fn exports_dir() -> Option<PathBuf> {
+    let stable = installations().into_iter().map(|x| x.join("Exports"));
+    let lazer = lazer_data_dirs().into_iter().map(|x| x.join("exports"));
+    stable
+        .chain(lazer)
+        .inspect(|x| debug!("looking for osu! exports in {}", x.to_string_lossy()))
+        .find(|x| x.is_dir())
+}
+
+/// Directories osu! (stable) may be installed in, most likely first
+fn installations() -> Vec<PathBuf> {
+    let mut dirs = registry_install_dirs();
+    // Default location of the installer
+    if let Some(local) = std::env::var_os("LOCALAPPDATA") {
+        dirs.push(PathBuf::from(local).join("osu!"));
+    }
+    // Wine prefixes on Linux
+    if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+        dirs.push(home.join(".local/share/osu-wine/osu!"));
+        if let Some(user) = std::env::var_os("USER") {
+            dirs.push(
+                home.join(".wine/drive_c/users")
+                    .join(user)
+                    .join("AppData/Local/osu!"),
+            );
+        }
+    }
+    dirs
+}
+
+/// Install directories from the file and URL handlers osu! registers on Windows
+#[cfg(windows)]
+fn registry_install_dirs() -> Vec<PathBuf> {
+    ["osu!", "osu"]
+        .iter()
+        .filter_map(|key| {
+            let output = std::process::Command::new("reg")
+                .arg("query")
+                .arg(format!(r"HKEY_CLASSES_ROOT\{}\shell\open\command", key))
+                .arg("/ve")
+                .output()
+                .ok()?;
+            // e.g. `(Default)    REG_SZ    "C:\Users\me\AppData\Local\osu!\osu!.exe" "%1"`
+            let text = String::from_utf8_lossy(&output.stdout);
+            let exe = PathBuf::from(text.split('"').nth(1)?);
+            Some(exe.parent()?.to_path_buf())
+        })
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn registry_install_dirs() -> Vec<PathBuf> {
+    vec![]
+}
+
+/// Data directories of osu!lazer, the one set in `storage.ini` (when the data was moved
+/// in the settings) first, then the default one
+fn lazer_data_dirs() -> Vec<PathBuf> {
+    let default = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|x| PathBuf::from(x).join("osu"))
+    } else if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|x| PathBuf::from(x).join("Library/Application Support/osu"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".local/share")))
+            .map(|x| x.join("osu"))
+    };
+    let Some(default) = default else {
+        return vec![];
+    };
+    // e.g. `FullPath = D:\osu-data`
+    let custom = std::fs::read_to_string(default.join("storage.ini"))
+        .ok()
+        .and_then(|text| {
+            text.lines()
+                .filter_map(|x| x.split_once('='))
+                .find(|(key, _)| key.trim() == "FullPath")
+                .map(|(_, value)| PathBuf::from(value.trim()))
+        })
+        .filter(|x| !x.as_os_str().is_empty());
+    custom.into_iter().chain([default]).collect()
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use git2::{Delta, DiffFormat, DiffOptions, Repository, Tree};
+use owo_colors::OwoColorize;
+
+use crate::{
+    Config,
+    repos::{self, ManagedRepo},
+};
+
+/// Prints the files that changed in the map directory between two revisions,
+/// followed by a textual diff of the changed difficulties
+pub fn run(config: &Config, managed: &ManagedRepo, from: &str, to: &str) -> anyhow::Result<()> {
+    let repo = managed.open(config)?;
+    let map_prefix = managed.map_prefix(config);
+    let old = map_tree(&repo, from, &map_prefix)?;
+    let new = map_tree(&repo, to, &map_prefix)?;
+
+    let mut options = DiffOptions::new();
+    options.context_lines(3);
+    let diff = repo
+        .diff_tree_to_tree(old.as_ref(), new.as_ref(), Some(&mut options))
+        .map_err(|x| anyhow!("Failed to diff {} and {}: {}", from, to, x))?;
+    if diff.deltas().len() == 0 {
+        println!("No changes in the map between {} and {}", from, to);
+        return Ok(());
+    }
+
+    println!("{}", format!("Changes from {} to {}:", from, to).bold());
+    for delta in diff.deltas() {
+        let path = delta
+            .new_file()
+            .path()
+            .or(delta.old_file().path())
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match delta.status() {
+            Delta::Added => println!("{} {}", "Added:".green(), path),
+            Delta::Deleted => println!("{} {}", "Removed:".red(), path),
+            Delta::Renamed => println!(
+                "{} {} -> {}",
+                "Renamed:".yellow(),
+                delta.old_file().path().unwrap_or(Path::new("")).display(),
+                path
+            ),
+            _ => println!("{} {}", "Modified:".yellow(), path),
+        }
+    }
+
+    // Only difficulties are worth reading line by line, other assets are binary or generated
+    diff.print(DiffFormat::Patch, |delta, _, line| {
+        let is_difficulty = delta
+            .new_file()
+            .path()
+            .or(delta.old_file().path())
+            .is_some_and(|x| x.extension().is_some_and(|x| x == "osu"));
+        if !is_difficulty {
+            return true;
+        }
+        let content = String::from_utf8_lossy(line.content());
+        let content = content.trim_end_matches(['\r', '\n']);
+        match line.origin() {
+            'F' => print!("\n{}\n", content.bold()),
+            'H' => println!("{}", content.cyan()),
+            '+' => println!("{}", format!("+{}", content).green()),
+            '-' => println!("{}", format!("-{}", content).red()),
+            ' ' => println!(" {}", content),
+            _ => println!("{}", content),
+        }
+        true
+    })
+    .map_err(|x| anyhow!("Failed to print diff: {}", x))?;
+    Ok(())
+}
+
+/// The map directory at `rev`, `None` if it didn't exist yet
+fn map_tree<'a>(
+    repo: &'a Repository,
+    rev: &str,
+    map_prefix: &Path,
+) -> anyhow::Result<Option<Tree<'a>>> {
+    let tree = repo
+        .revparse_single(rev)
+        .and_then(|x| x.peel_to_tree())
+        .map_err(|x| anyhow!("Failed to read {}: {}", rev, x))?;
+    match repos::subtree(repo, &tree, map_prefix) {
+        Ok(tree) => Ok(Some(tree)),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => anyhow::bail!("Failed to read the map directory at {}: {}", rev, err),
+    }
+}
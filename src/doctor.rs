@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use owo_colors::OwoColorize;
+use serde_json::{Value, json};
+
+use crate::{
+    Args, check_directory, dir_or_cwd, exports_dir,
+    repos::{self, OutputFormat},
+};
+
+enum Check {
+    Pass(String),
+    Warn(String, &'static str),
+    Fail(String, &'static str),
+}
+
+/// Runs all checks and prints a report, failing if any check failed
+pub fn run(args: &Args, format: OutputFormat) -> anyhow::Result<()> {
+    let exports = exports_dir(args);
+    let repos = dir_or_cwd(&args.repositories);
+
+    let checks = [
+        check_git_identity(),
+        check_writable_directory(&exports, "Exports"),
+        check_writable_directory(&repos, "Repositories"),
+        check_watcher(&exports),
+        check_overlap(&exports, &repos),
+    ];
+
+    let failed = checks
+        .iter()
+        .filter(|x| matches!(x, Check::Fail(..)))
+        .count();
+    if format != OutputFormat::Table {
+        let rows: Vec<_> = checks
+            .iter()
+            .map(|check| match check {
+                Check::Pass(msg) => vec![json!("pass"), json!(msg), Value::Null],
+                Check::Warn(msg, hint) => vec![json!("warn"), json!(msg), json!(hint)],
+                Check::Fail(msg, hint) => vec![json!("fail"), json!(msg), json!(hint)],
+            })
+            .collect();
+        repos::print_rows(format, &["status", "message", "hint"], &rows);
+    } else {
+        for check in checks {
+            match check {
+                Check::Pass(msg) => println!("[{}] {}", "+".green(), msg),
+                Check::Warn(msg, hint) => {
+                    println!("[{}] {}", "!".yellow(), msg);
+                    println!("    {}", hint.dimmed());
+                }
+                Check::Fail(msg, hint) => {
+                    println!("[{}] {}", "x".red(), msg);
+                    println!("    {}", hint.dimmed());
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    if format == OutputFormat::Table {
+        println!("Everything looks good!");
+    }
+    Ok(())
+}
+
+fn check_git_identity() -> Check {
+    let hint = "set it with `git config --global user.name <NAME>` and `git config --global user.email <EMAIL>`";
+    let config = match git2::Config::open_default() {
+        Ok(c) => c,
+        Err(err) => return Check::Fail(format!("Failed to read git config: {}", err), hint),
+    };
+    match (
+        config.get_string("user.name"),
+        config.get_string("user.email"),
+    ) {
+        (Ok(name), Ok(email)) => Check::Pass(format!("Git identity: {} <{}>", name, email)),
+        _ => Check::Warn(
+            "Git identity is not configured, commits will use the default author".to_string(),
+            hint,
+        ),
+    }
+}
+
+fn check_writable_directory(path: &Path, name: &str) -> Check {
+    let path_str = path.to_string_lossy();
+    if let Err(err) = check_directory(path, name) {
+        return Check::Fail(
+            format!("{} ({})", err, path_str),
+            "create the directory or point gitosu to the right one with --exports/--repositories",
+        );
+    }
+
+    let probe = path.join(".gitosu-doctor");
+    match std::fs::write(&probe, b"") {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Check::Pass(format!("{} directory is writable ({})", name, path_str))
+        }
+        Err(err) => Check::Fail(
+            format!("{} directory is not writable ({}): {}", name, path_str, err),
+            "check the directory permissions and the user gitosu runs as",
+        ),
+    }
+}
+
+fn check_watcher(exports: &Path) -> Check {
+    let hint = "the filesystem may not support change notifications, use `gitosu import` instead";
+    let mut watcher = match RecommendedWatcher::new(|_| {}, notify::Config::default()) {
+        Ok(w) => w,
+        Err(err) => return Check::Fail(format!("Failed to create file watcher: {}", err), hint),
+    };
+    match watcher.watch(exports, RecursiveMode::NonRecursive) {
+        Ok(_) => Check::Pass("Exports directory can be watched".to_string()),
+        Err(err) => Check::Fail(format!("Failed to watch exports directory: {}", err), hint),
+    }
+}
+
+fn check_overlap(exports: &Path, repos: &Path) -> Check {
+    let same = match (exports.canonicalize(), repos.canonicalize()) {
+        (Ok(e), Ok(r)) => e == r,
+        _ => false,
+    };
+    if same {
+        Check::Warn(
+            "Exports and repositories are the same directory".to_string(),
+            "this works, but keeping repositories in a separate directory with --repositories is cleaner",
+        )
+    } else {
+        Check::Pass("Exports and repositories are separate directories".to_string())
+    }
+}
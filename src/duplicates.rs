@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use git2::{ObjectType, Oid, TreeWalkMode, TreeWalkResult};
+use owo_colors::OwoColorize;
+
+use crate::{
+    Config,
+    repos::{self, ManagedRepo},
+};
+
+/// Differently named files with the same contents
+pub struct Duplicate {
+    pub size: u64,
+    /// Paths relative to the map directory, sorted
+    pub paths: Vec<String>,
+}
+
+impl Duplicate {
+    /// Space taken by all copies but one
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Finds files with identical contents in the map directory committed at HEAD,
+/// most wasted space first
+///
+/// Identical files share a blob in git, so grouping by blob id needs no extra hashing
+pub fn find(config: &Config, managed: &ManagedRepo) -> anyhow::Result<Vec<Duplicate>> {
+    let repo = managed.open(config)?;
+    let tree = repo
+        .head()
+        .and_then(|x| x.peel_to_tree())
+        .map_err(|x| anyhow!("Failed to read HEAD: {}", x))?;
+    let map_tree = repos::subtree(&repo, &tree, &managed.map_prefix(config))
+        .map_err(|x| anyhow!("Failed to read the map directory: {}", x))?;
+
+    let mut blobs: HashMap<Oid, Vec<String>> = HashMap::new();
+    map_tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob)
+            && let Some(name) = entry.name()
+        {
+            blobs
+                .entry(entry.id())
+                .or_default()
+                .push(format!("{}{}", dir, name));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let odb = repo.odb()?;
+    let mut duplicates = vec![];
+    for (id, mut paths) in blobs {
+        if paths.len() < 2 {
+            continue;
+        }
+        let (size, _) = odb
+            .read_header(id)
+            .map_err(|x| anyhow!("Failed to read {}: {}", paths[0], x))?;
+        paths.sort();
+        duplicates.push(Duplicate {
+            size: size as u64,
+            paths,
+        });
+    }
+    duplicates.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.paths.cmp(&b.paths)));
+    Ok(duplicates)
+}
+
+/// Prints the duplicate files of every given repository and the space they waste
+pub fn run(config: &Config, managed: &[ManagedRepo]) -> anyhow::Result<()> {
+    let mut total = 0;
+    let mut failed = 0;
+    for repo in managed {
+        match find(config, repo) {
+            Ok(duplicates) if duplicates.is_empty() => {}
+            Ok(duplicates) => {
+                println!("{}", repo.name.bold());
+                for duplicate in &duplicates {
+                    println!(
+                        "  {} copies of {}: {}",
+                        duplicate.paths.len(),
+                        format_size(duplicate.size),
+                        duplicate.paths.join(", ")
+                    );
+                    total += duplicate.wasted();
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                println!("[{}] {}: {}", "x".red(), repo.name, err);
+            }
+        }
+    }
+
+    match total {
+        0 => println!("No duplicate files found"),
+        total => println!(
+            "Duplicate files waste {} in the map directories and exported archives \
+             (git stores each of them once)",
+            format_size(total)
+        ),
+    }
+    if failed > 0 {
+        anyhow::bail!("{} repositories couldn't be checked", failed);
+    }
+    Ok(())
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,73 @@
+use std::sync::mpsc::{self, Receiver, TrySendError};
+
+use log::warn;
+use owo_colors::OwoColorize;
+
+use crate::{EventOverflow, metrics};
+
+type Event = notify::Result<notify::Event>;
+
+/// Channel between a filesystem watcher and its loop, holding at most `size` events
+/// that haven't been handled yet, or any number without a size
+///
+/// When it's full, [`EventOverflow::Block`] makes the watcher's thread wait until the
+/// loop catches up, [`EventOverflow::Drop`] discards new events with a warning
+pub fn channel(
+    size: Option<usize>,
+    overflow: EventOverflow,
+) -> (impl FnMut(Event) + Send + 'static, Receiver<Event>) {
+    enum Sender {
+        Unbounded(mpsc::Sender<Event>),
+        Bounded(mpsc::SyncSender<Event>),
+    }
+    let (sender, receiver) = match size {
+        None => {
+            let (tx, rx) = mpsc::channel();
+            (Sender::Unbounded(tx), rx)
+        }
+        Some(size) => {
+            let (tx, rx) = mpsc::sync_channel(size);
+            (Sender::Bounded(tx), rx)
+        }
+    };
+
+    // Events dropped since the queue last had room
+    let mut dropped = 0u64;
+    let handler = move |event: Event| {
+        // Sending only fails once the loop stopped, and then nobody needs the event
+        let tx = match &sender {
+            Sender::Unbounded(tx) => {
+                let _ = tx.send(event);
+                return;
+            }
+            Sender::Bounded(tx) if overflow == EventOverflow::Block => {
+                let _ = tx.send(event);
+                return;
+            }
+            Sender::Bounded(tx) => tx,
+        };
+        match tx.try_send(event) {
+            Err(TrySendError::Full(_)) => {
+                if dropped == 0 {
+                    warn!(
+                        "[{}] The event queue is full, dropping filesystem events until imports catch up",
+                        "!".yellow()
+                    );
+                }
+                dropped += 1;
+                metrics::inc(&metrics::EVENTS_DROPPED);
+            }
+            Err(TrySendError::Disconnected(_)) => {}
+            Ok(()) if dropped > 0 => {
+                warn!(
+                    "[{}] Dropped {} filesystem events, exports made meanwhile may not have been imported",
+                    "!".yellow(),
+                    dropped
+                );
+                dropped = 0;
+            }
+            Ok(()) => {}
+        }
+    };
+    (handler, receiver)
+}
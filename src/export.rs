@@ -0,0 +1,261 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use git2::{ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use log::{debug, info};
+use owo_colors::OwoColorize;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    Config,
+    manifest::{self, Manifest},
+    repos,
+};
+
+/// Compression method used for exported archives
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// No compression
+    Store,
+    /// Standard zip compression, supported by osu!
+    Deflate,
+    /// Better compression, NOT supported by osu!
+    Zstd,
+}
+
+impl Compression {
+    fn method(self) -> CompressionMethod {
+        match self {
+            Self::Store => CompressionMethod::Stored,
+            Self::Deflate => CompressionMethod::Deflated,
+            Self::Zstd => CompressionMethod::Zstd,
+        }
+    }
+
+    /// Whether osu! can import archives using this method
+    pub fn osu_compatible(self) -> bool {
+        matches!(self, Self::Store | Self::Deflate)
+    }
+}
+
+/// Packages everything in `map_path` into an archive at `output`,
+/// in the order of the last imported archive if it was recorded
+pub fn export_map(
+    map_path: &Path,
+    output: &Path,
+    compression: Compression,
+    level: Option<i64>,
+) -> anyhow::Result<()> {
+    let mut files = vec![];
+    collect_files(map_path, &mut files)
+        .map_err(|x| anyhow!("Failed to read the map directory: {}", x))?;
+    files.sort();
+
+    let mut entries = vec![];
+    for path in files {
+        let relative = path.strip_prefix(map_path)?;
+        entries.push((manifest::archive_name(relative), path));
+    }
+    // Raw repositories have nothing but the map, and no manifest
+    let manifest = match map_path.parent() {
+        Some(repo_path) if !repos::is_raw(map_path) => Manifest::read(repo_path)?,
+        _ => None,
+    };
+    write_archive(
+        output,
+        compression,
+        level,
+        manifest,
+        entries,
+        |path, zip| {
+            let mut source = File::open(path).map_err(|x| anyhow!("Failed to open file: {}", x))?;
+            std::io::copy(&mut source, zip)?;
+            Ok(())
+        },
+    )
+}
+
+/// Packages every repository into `<name>.osz` in `output`, e.g. to import a whole
+/// collection into a fresh osu! install
+///
+/// Repositories with uncommitted changes are skipped unless `--force` is given,
+/// since their working tree may not be what was committed
+pub fn export_all(
+    config: &Config,
+    output: &Path,
+    compression: Compression,
+    level: Option<i64>,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(output)
+        .map_err(|x| anyhow!("Failed to create {}: {}", output.to_string_lossy(), x))?;
+    let managed = repos::scan(config)?;
+    let mut exported = HashSet::new();
+    let (mut succeeded, mut skipped, mut failed) = (0, 0, 0);
+    for repo in &managed {
+        let file_name = format!("{}.osz", repo.name);
+        let result = match repo.open(config) {
+            Ok(git) if repo.is_dirty(config, &git) && !config.force => {
+                skipped += 1;
+                println!(
+                    "[{}] {}: skipped, it has uncommitted changes (use --force to export anyway)",
+                    "!".yellow(),
+                    repo.name
+                );
+                continue;
+            }
+            // Maps with the same name in different mode directories
+            Ok(_) if !exported.insert(file_name.clone()) => Err(anyhow!(
+                "another repository was already exported as {}",
+                file_name
+            )),
+            Ok(_) => export_map(
+                &repo.map_path(),
+                &output.join(&file_name),
+                compression,
+                level,
+            ),
+            Err(err) => Err(err),
+        };
+        match result {
+            Ok(()) => {
+                succeeded += 1;
+                println!("[{}] {}", "+".green(), repo.name);
+            }
+            Err(err) => {
+                failed += 1;
+                println!("[{}] {}: {}", "x".red(), repo.name, err);
+            }
+        }
+    }
+
+    println!(
+        "Exported {} of {} repositories into {} ({} skipped, {} failed)",
+        succeeded,
+        managed.len(),
+        output.to_string_lossy(),
+        skipped,
+        failed
+    );
+    if failed > 0 {
+        anyhow::bail!("{} repositories couldn't be exported", failed);
+    }
+    Ok(())
+}
+
+/// Packages the map directory at `map_prefix` as it was committed at `rev`
+pub fn export_revision(
+    repo: &Repository,
+    rev: &str,
+    map_prefix: &Path,
+    output: &Path,
+    compression: Compression,
+    level: Option<i64>,
+) -> anyhow::Result<()> {
+    let tree = repo
+        .revparse_single(rev)
+        .and_then(|x| x.peel_to_tree())
+        .map_err(|x| anyhow!("Failed to read {}: {}", rev, x))?;
+    let map_tree = repos::subtree(repo, &tree, map_prefix)
+        .map_err(|_| anyhow!("{} has no map directory", rev))?;
+
+    let mut entries = vec![];
+    map_tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob)
+            && let Some(name) = entry.name()
+        {
+            // Tree paths already use forward slashes
+            entries.push((format!("{}{}", dir, name), entry.id()));
+        }
+        TreeWalkResult::Ok
+    })?;
+    entries.sort();
+    let manifest = match tree.get_path(&manifest::tree_path(map_prefix)) {
+        Ok(entry) => {
+            let blob = entry
+                .to_object(repo)
+                .and_then(|x| x.peel_to_blob())
+                .map_err(|x| anyhow!("Failed to read archive manifest: {}", x))?;
+            Some(Manifest::parse(blob.content())?)
+        }
+        Err(_) => None,
+    };
+
+    write_archive(output, compression, level, manifest, entries, |id, zip| {
+        let blob = repo
+            .find_blob(*id)
+            .map_err(|x| anyhow!("Failed to read file from git: {}", x))?;
+        zip.write_all(blob.content())?;
+        Ok(())
+    })
+}
+
+/// Writes an archive with the given entries, `write` copies the contents of an entry into it
+fn write_archive<T>(
+    output: &Path,
+    compression: Compression,
+    level: Option<i64>,
+    manifest: Option<Manifest>,
+    mut entries: Vec<(String, T)>,
+    mut write: impl FnMut(&T, &mut ZipWriter<File>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let is_osz = output.extension().map(|x| x == "osz").unwrap_or(false);
+    if is_osz && !compression.osu_compatible() {
+        anyhow::bail!(
+            "osu! can't import {:?} compressed archives, use a .zip output or another compression method",
+            compression
+        );
+    }
+    if entries.is_empty() {
+        anyhow::bail!("Map directory is empty!");
+    }
+
+    info!(
+        "[{}] Exporting {} files into {}...",
+        "+".green(),
+        entries.len(),
+        output.to_string_lossy().green()
+    );
+    let file = File::create(output).map_err(|x| anyhow!("Failed to create archive: {}", x))?;
+    let mut zip = ZipWriter::new(file);
+    if let Some(manifest) = manifest {
+        debug!("using the entry order of the imported archive");
+        manifest.order(&mut entries);
+        zip.set_comment(manifest.comment);
+    }
+    let options = SimpleFileOptions::default()
+        .compression_method(compression.method())
+        .compression_level(level);
+
+    for (name, entry) in entries {
+        debug!("adding {}", name);
+        zip.start_file(name, options)
+            .map_err(|x| anyhow!("Failed to add file to archive: {}", x))?;
+        write(&entry, &mut zip).map_err(|x| anyhow!("Failed to write file to archive: {}", x))?;
+    }
+    zip.finish()
+        .map_err(|x| anyhow!("Failed to finish archive: {}", x))?;
+
+    Ok(())
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // The map is the repository root in raw repositories
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,70 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use log::{debug, warn};
+use owo_colors::OwoColorize;
+
+/// Extensions of the sound files `--import-sound` plays instead of running them
+const SOUND_EXTENSIONS: [&str; 5] = ["wav", "mp3", "ogg", "flac", "aiff"];
+
+/// Plays the `--import-sound` sound file at `path`, or runs it if it isn't a sound
+///
+/// Runs in the background, the import doesn't wait for the sound to finish
+pub fn import_complete(path: &Path) {
+    let is_sound = path
+        .extension()
+        .is_some_and(|x| SOUND_EXTENSIONS.contains(&x.to_string_lossy().to_lowercase().as_str()));
+    let commands = match is_sound {
+        true => players(path),
+        false => vec![Command::new(path)],
+    };
+    for mut command in commands {
+        let spawned = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        match spawned {
+            Ok(mut child) => {
+                // Waited on so finished players don't linger as zombies
+                std::thread::spawn(move || child.wait());
+                return;
+            }
+            Err(err) => debug!("failed to run {:?}: {}", command.get_program(), err),
+        }
+    }
+    warn!(
+        "[{}] Failed to play {}, no player could be started",
+        "!".yellow(),
+        path.to_string_lossy().yellow()
+    );
+}
+
+/// Commands that can play the sound file at `path` on this platform, most likely first
+fn players(path: &Path) -> Vec<Command> {
+    let command = |program: &str, args: &[&str]| {
+        let mut command = Command::new(program);
+        command.args(args).arg(path);
+        command
+    };
+    if cfg!(target_os = "windows") {
+        // Only plays .wav files, which is what short notification sounds usually are
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-Command"]).arg(format!(
+            "(New-Object Media.SoundPlayer '{}').PlaySync()",
+            path.to_string_lossy().replace('\'', "''")
+        ));
+        vec![command]
+    } else if cfg!(target_os = "macos") {
+        vec![command("afplay", &[])]
+    } else {
+        vec![
+            command("paplay", &[]),
+            command("pw-play", &[]),
+            command("aplay", &["-q"]),
+            command("ffplay", &["-nodisp", "-autoexit", "-loglevel", "quiet"]),
+        ]
+    }
+}
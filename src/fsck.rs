@@ -0,0 +1,120 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use owo_colors::OwoColorize;
+
+use serde_json::{Value, json};
+
+use crate::{
+    Config,
+    repos::{self, OutputFormat},
+};
+
+/// Checks every repository in the repositories directory, failing if any of them is broken
+pub fn run(config: &Config, format: OutputFormat) -> anyhow::Result<()> {
+    // Reading an object verifies its contents match its hash
+    git2::opts::strict_hash_verification(true);
+
+    let managed = repos::scan(config)?;
+    if managed.is_empty() && format == OutputFormat::Table {
+        println!(
+            "No repositories found in {}",
+            config.repos.to_string_lossy()
+        );
+        return Ok(());
+    }
+    // All maps share one repository in monorepo mode
+    let repositories = match config.monorepo {
+        true if managed.is_empty() => vec![],
+        true => vec![(
+            config.repos.to_string_lossy().to_string(),
+            Repository::open(&config.repos),
+        )],
+        false => managed
+            .iter()
+            .map(|x| (x.name.clone(), Repository::open(&x.path)))
+            .collect(),
+    };
+
+    let (mut failed, mut rows) = (0, vec![]);
+    for (name, repo) in repositories {
+        let result = repo
+            .map_err(|x| anyhow!("Failed to open repository: {}", x))
+            .and_then(|repo| check_repository(&repo));
+        if result.is_err() {
+            failed += 1;
+        }
+        match (format, result) {
+            (OutputFormat::Table, Ok(objects)) => {
+                println!("[{}] {} ({} objects)", "+".green(), name, objects)
+            }
+            (OutputFormat::Table, Err(err)) => println!("[{}] {}: {}", "x".red(), name, err),
+            (_, Ok(objects)) => rows.push(vec![json!(name), json!(objects), Value::Null]),
+            (_, Err(err)) => rows.push(vec![json!(name), Value::Null, json!(err.to_string())]),
+        }
+    }
+    if format != OutputFormat::Table {
+        repos::print_rows(format, &["repository", "objects", "error"], &rows);
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} repositories are broken", failed);
+    }
+    if format == OutputFormat::Table {
+        println!("All repositories are healthy!");
+    }
+    Ok(())
+}
+
+/// Reads every object reachable from a reference, returning how many there are
+fn check_repository(repo: &Repository) -> anyhow::Result<usize> {
+    let odb = repo.odb()?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_glob("refs/*")?;
+    if let Ok(head) = repo.head()
+        && let Some(target) = head.target()
+    {
+        revwalk.push(target)?;
+    }
+
+    let mut seen: HashSet<Oid> = HashSet::new();
+    for id in revwalk {
+        let id = id.map_err(|x| anyhow!("Broken history: {}", x))?;
+        let commit = repo
+            .find_commit(id)
+            .map_err(|x| anyhow!("Commit {} is unreadable: {}", id, x))?;
+        seen.insert(id);
+        let tree = commit
+            .tree()
+            .map_err(|x| anyhow!("Tree of commit {} is missing: {}", id, x))?;
+        if !seen.insert(tree.id()) {
+            continue;
+        }
+
+        let mut error = None;
+        let walked = tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            // Submodules point to commits in other repositories
+            if entry.kind() == Some(ObjectType::Commit) || !seen.insert(entry.id()) {
+                return TreeWalkResult::Skip;
+            }
+            if let Err(err) = odb.read(entry.id()) {
+                error = Some(anyhow!(
+                    "{}{} ({}) in commit {} is missing or corrupted: {}",
+                    dir,
+                    entry.name().unwrap_or("?"),
+                    entry.id(),
+                    id,
+                    err.message()
+                ));
+                return TreeWalkResult::Abort;
+            }
+            TreeWalkResult::Ok
+        });
+        if let Some(err) = error {
+            return Err(err);
+        }
+        walked.map_err(|x| anyhow!("Tree of commit {} is broken: {}", id, x))?;
+    }
+    Ok(seen.len())
+}
@@ -0,0 +1,100 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::anyhow;
+use git2::Repository;
+use serde_json::{Value, json};
+
+const API: &str = "https://api.github.com";
+
+/// A release to create for a commit, with the imported archive attached
+pub struct Release<'a> {
+    /// `owner/repo` on GitHub
+    pub repo: &'a str,
+    pub token: &'a str,
+    pub tag: String,
+    pub name: String,
+    pub body: String,
+    /// Branch the tag is created on when the release gets published
+    pub branch: String,
+    pub osz: &'a Path,
+    pub asset_name: String,
+}
+
+/// Creates a draft release with the archive attached, returning its URL
+///
+/// Releases are drafts because the commit isn't pushed yet, publishing one after pushing
+/// creates its tag on the branch
+pub fn create_release(release: &Release) -> anyhow::Result<String> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(10))
+        .timeout_read(Duration::from_secs(60))
+        .timeout_write(Duration::from_secs(300))
+        .user_agent(concat!("gitosu/", env!("CARGO_PKG_VERSION")))
+        .build();
+    let auth = format!("Bearer {}", release.token);
+
+    let created: Value = agent
+        .post(&format!("{}/repos/{}/releases", API, release.repo))
+        .set("Authorization", &auth)
+        .set("Accept", "application/vnd.github+json")
+        .send_json(json!({
+            "tag_name": release.tag,
+            "target_commitish": release.branch,
+            "name": release.name,
+            "body": release.body,
+            "draft": true,
+        }))
+        .map_err(|x| api_error("Failed to create release", x))?
+        .into_json()
+        .map_err(|x| anyhow!("Failed to read the created release: {}", x))?;
+    let url = created["html_url"].as_str().unwrap_or_default().to_string();
+    // The upload URL is a template ending with {?name,label}
+    let upload_url = created["upload_url"]
+        .as_str()
+        .and_then(|x| x.split('{').next())
+        .ok_or(anyhow!(
+            "GitHub didn't return an upload URL for the release"
+        ))?;
+
+    let osz = std::fs::read(release.osz).map_err(|x| anyhow!("Failed to read .osz: {}", x))?;
+    agent
+        .post(upload_url)
+        .query("name", &release.asset_name)
+        .set("Authorization", &auth)
+        .set("Accept", "application/vnd.github+json")
+        .set("Content-Type", "application/octet-stream")
+        .send_bytes(&osz)
+        .map_err(|x| api_error("Failed to upload .osz to the release", x))?;
+
+    Ok(url)
+}
+
+/// Finds the `owner/repo` of the `origin` remote if it's hosted on GitHub
+pub fn origin_repo(repo: &Repository) -> Option<String> {
+    let remote = repo.find_remote("origin").ok()?;
+    let url = remote.url()?;
+    let path = url
+        .strip_prefix("https://github.com/")
+        .or_else(|| url.strip_prefix("ssh://git@github.com/"))
+        .or_else(|| url.strip_prefix("git@github.com:"))?;
+    let path = path.trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    match path.split('/').count() {
+        2 => Some(path.to_string()),
+        _ => None,
+    }
+}
+
+fn api_error(context: &str, err: ureq::Error) -> anyhow::Error {
+    match err {
+        ureq::Error::Status(code, response) => {
+            let message = response
+                .into_json::<Value>()
+                .ok()
+                .and_then(|x| x["message"].as_str().map(|x| x.to_string()))
+                .unwrap_or_default();
+            anyhow!("{}: GitHub returned {} {}", context, code, message)
+        }
+        err => anyhow!("{}: {}", context, err),
+    }
+}
@@ -0,0 +1,99 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, warn};
+use owo_colors::OwoColorize;
+
+/// How long the watcher may go without a heartbeat before it counts as stuck,
+/// long enough for big imports which block the watcher loop
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Seconds since the Unix epoch of the last watcher loop iteration, 0 before the first
+static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Records that the watcher loop is still running
+pub fn beat() {
+    HEARTBEAT.store(now(), Ordering::Relaxed);
+}
+
+/// Whether the watcher loop ran recently
+fn alive() -> bool {
+    let last = HEARTBEAT.load(Ordering::Relaxed);
+    last != 0 && now().saturating_sub(last) <= HEARTBEAT_TIMEOUT.as_secs()
+}
+
+/// Spawns a thread answering `/healthz` (the watcher is running) and `/readyz`
+/// (every directory in `dirs` is accessible) on `addr`
+pub fn spawn_server(addr: SocketAddr, dirs: Vec<PathBuf>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|x| anyhow::anyhow!("Failed to bind health address {}: {}", addr, x))?;
+    info!(
+        "[{}] Serving health checks on {}",
+        "i".cyan(),
+        format!("http://{}/healthz", addr).cyan()
+    );
+    beat();
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(err) => {
+                    debug!("health connection failed: {}", err);
+                    continue;
+                }
+            };
+            let mut buf = [0u8; 1024];
+            let read = stream.read(&mut buf).unwrap_or(0);
+            // e.g. `GET /healthz HTTP/1.1`
+            let request = String::from_utf8_lossy(&buf[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+            let (status, body) = match path {
+                "/healthz" if alive() => ("200 OK", "ok\n".to_string()),
+                "/healthz" => (
+                    "503 Service Unavailable",
+                    format!(
+                        "watcher stuck for more than {}s\n",
+                        HEARTBEAT_TIMEOUT.as_secs()
+                    ),
+                ),
+                "/readyz" => match dirs.iter().find(|x| std::fs::read_dir(x).is_err()) {
+                    None => ("200 OK", "ok\n".to_string()),
+                    Some(dir) => (
+                        "503 Service Unavailable",
+                        format!("{} is not accessible\n", dir.to_string_lossy()),
+                    ),
+                },
+                _ => ("404 Not Found", "not found\n".to_string()),
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()) {
+                warn!(
+                    "[{}] Failed to write health response: {}",
+                    "!".yellow(),
+                    err
+                );
+            }
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::{collections::BTreeSet, io::Read, path::Path, process::Command};
+
+use anyhow::anyhow;
+use git2::Repository;
+use log::debug;
+
+use crate::metadata;
+
+const BLOCK_START: &str = "# gitosu --smart-lfs start";
+const BLOCK_END: &str = "# gitosu --smart-lfs end";
+const ATTRIBUTES: &str = "filter=lfs diff=lfs merge=lfs -text";
+
+/// Extensions of archives that are always binary
+const ARCHIVE_EXTENSIONS: [&str; 2] = ["osz", "olz"];
+
+/// Fails if Git LFS isn't installed, `--smart-lfs` can't do anything without it
+pub fn check_installed() -> anyhow::Result<()> {
+    let output = Command::new("git")
+        .args(["lfs", "version"])
+        .output()
+        .map_err(|x| anyhow!("Failed to run git: {}", x))?;
+    if !output.status.success() {
+        anyhow::bail!("--smart-lfs needs Git LFS, install it from https://git-lfs.com");
+    }
+    Ok(())
+}
+
+/// Tracks the binary files in `repo_path` (the directory containing `map/`) with LFS,
+/// rewriting the rules gitosu manages in its `.gitattributes`
+///
+/// Media files and archives are tracked by extension, other files by path if their
+/// content looks binary. Rules outside of gitosu's block are left alone
+pub fn track(repo: &Repository, repo_path: &Path) -> anyhow::Result<()> {
+    install(repo)?;
+    let mut rules = BTreeSet::new();
+    classify(repo_path, repo_path, &mut rules)?;
+
+    let path = repo_path.join(".gitattributes");
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => anyhow::bail!("Failed to read .gitattributes: {}", err),
+    };
+    let mut text = String::new();
+    let mut in_block = false;
+    for line in existing.lines() {
+        match line.trim() {
+            BLOCK_START => in_block = true,
+            BLOCK_END => in_block = false,
+            _ if !in_block => text += &format!("{}\n", line),
+            _ => {}
+        }
+    }
+    if !rules.is_empty() {
+        if !text.is_empty() && !text.ends_with("\n\n") {
+            text += "\n";
+        }
+        text += &format!("{}\n", BLOCK_START);
+        for rule in &rules {
+            text += &format!("{} {}\n", rule, ATTRIBUTES);
+        }
+        text += &format!("{}\n", BLOCK_END);
+    }
+    if text != existing {
+        std::fs::write(&path, text)
+            .map_err(|x| anyhow!("Failed to write .gitattributes: {}", x))?;
+    }
+    Ok(())
+}
+
+/// Stages everything in `pathspec` with git itself, libgit2 can't run the LFS filter
+pub fn stage(repo: &Repository, pathspec: &str) -> anyhow::Result<()> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo.workdir().unwrap_or(repo.path()))
+        .args(["add", "--all", "--", pathspec])
+        .output()
+        .map_err(|x| anyhow!("Failed to run git: {}", x))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git add exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // The index changed behind libgit2's back
+    repo.index()
+        .and_then(|mut x| x.read(true))
+        .map_err(|x| anyhow!("Failed to reload the index: {}", x))
+}
+
+/// Sets up the LFS filter in `repo` unless it's already configured, e.g. globally
+fn install(repo: &Repository) -> anyhow::Result<()> {
+    let configured = repo
+        .config()
+        .and_then(|x| x.get_string("filter.lfs.clean"))
+        .is_ok();
+    if configured {
+        return Ok(());
+    }
+    debug!("installing git lfs in {}", repo.path().to_string_lossy());
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["lfs", "install", "--local"])
+        .output()
+        .map_err(|x| anyhow!("Failed to run git: {}", x))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git lfs install exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Adds a rule for every binary file in `dir` to `rules`, relative to `root`
+fn classify(root: &Path, dir: &Path, rules: &mut BTreeSet<String>) -> anyhow::Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|x| anyhow!("Failed to read {}: {}", dir.to_string_lossy(), x))?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        if name == ".git" || name == ".gitosu" {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            classify(root, &path, rules)?;
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+        let extension = path.extension().map(|x| x.to_string_lossy().to_string());
+        let key = metadata::asset_key(&name.to_string_lossy());
+        let by_extension = metadata::is_media(&key)
+            || extension
+                .as_deref()
+                .is_some_and(|x| ARCHIVE_EXTENSIONS.contains(&x.to_lowercase().as_str()));
+        match extension {
+            // Extensions keep their case, gitattributes patterns are case-sensitive
+            Some(extension) if by_extension => {
+                rules.insert(format!("*.{}", pattern_escape(&extension)));
+            }
+            _ if !metadata::is_text(&key) && looks_binary(&path) => {
+                let relative = path.strip_prefix(root).unwrap_or(&path);
+                let relative = relative.to_string_lossy().replace('\\', "/");
+                rules.insert(format!("/{}", pattern_escape(&relative)));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether the start of the file contains a NUL byte, the same check git uses
+fn looks_binary(path: &Path) -> bool {
+    let mut start = vec![];
+    std::fs::File::open(path)
+        .and_then(|x| x.take(8000).read_to_end(&mut start))
+        .is_ok_and(|_| start.contains(&0))
+}
+
+/// Escapes the characters gitattributes patterns can't contain as they are,
+/// the same way `git lfs track` does
+fn pattern_escape(pattern: &str) -> String {
+    pattern.replace(' ', "[[:space:]]").replace('#', "\\#")
+}
@@ -1,7 +1,22 @@
-use std::{fs::File, path::PathBuf, sync::Arc};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, hash_map::Entry},
+    ffi::OsStr,
+    fs::File,
+    io::{IsTerminal, Read, Write},
+    net::SocketAddr,
+    num::NonZeroUsize,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::RecvTimeoutError,
+    },
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::anyhow;
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use git2::Repository;
 use log::{debug, error, info, trace, warn};
 use notify::{
@@ -10,8 +25,44 @@ use notify::{
 };
 use owo_colors::OwoColorize;
 use regex::Regex;
+use tracing::{field, info_span};
 use zip::ZipArchive;
 
+use crate::{
+    export::Compression, metadata::MetadataFrom, repo_lock::RepoLock, repos::OutputFormat,
+};
+
+mod archive;
+mod audit;
+mod config;
+mod detect;
+mod diff;
+mod doctor;
+mod duplicates;
+mod event_queue;
+mod export;
+mod feedback;
+mod fsck;
+mod github;
+mod health;
+mod lfs;
+mod manifest;
+mod message;
+mod metadata;
+mod metrics;
+mod name_script;
+mod normalize;
+mod readme;
+mod repo_lock;
+mod repo_watcher;
+mod repos;
+mod reset;
+mod squash;
+mod store;
+
+/// Hidden/lock files and common partial download suffixes
+const DEFAULT_IGNORE_PATTERN: &str = r"^[.~]|(?i)\.(part|partial|tmp|crdownload|download)(\.osz)?$";
+
 /// Automatically converts osu! exports into git commits
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -20,6 +71,11 @@ struct Args {
     #[arg(short, long)]
     exports: Option<PathBuf>,
 
+    /// Use the Exports directory of the osu! installation (found through the registry
+    /// or common install locations), or osu!lazer's exports directory, unless --exports is given
+    #[arg(long, action)]
+    auto_detect: bool,
+
     /// Repositories directory
     #[arg(short, long)]
     repositories: Option<PathBuf>,
@@ -29,22 +85,707 @@ struct Args {
     #[arg(short, long, action)]
     keep_latest_osz: bool,
 
+    /// Run `git gc` after an import once the repository has too many loose objects
+    /// (needs git to be installed)
+    #[arg(long, action)]
+    auto_gc: bool,
+
+    /// Number of loose objects that triggers --auto-gc
+    #[arg(
+        long,
+        value_name = "OBJECTS",
+        default_value_t = 1000,
+        requires = "auto_gc"
+    )]
+    gc_threshold: usize,
+
+    /// Warn when a repository's .git directory grows past this many megabytes after a commit,
+    /// e.g. because of --keep-latest-osz
+    #[arg(long, value_name = "MB")]
+    confirm_large_repo: Option<u64>,
+
+    /// Ask before creating a repository when there are already this many
+    /// (refuse when not interactive), e.g. to catch pointing gitosu at the wrong folder
+    #[arg(long, value_name = "COUNT")]
+    max_repos: Option<usize>,
+
+    /// Path of the kept .osz in the repository, supports {name}, {date}, {time}
+    /// and {title}, {artist}, {creator}, {version}, {mode}, {beatmap_id}, {beatmap_set_id}
+    /// of the difficulty --metadata-from selects
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{name}.osz",
+        requires = "keep_latest_osz"
+    )]
+    keep_osz_path: String,
+
+    /// Treat the repositories directory as a single git repository
+    /// and import every map into its own subdirectory
+    #[arg(long, action)]
+    monorepo: bool,
+
+    /// Import into an existing repository anywhere under the repositories directory
+    /// whose committed map is the same as the archive's (same beatmap set, or artist,
+    /// title and creator), when no repository has the computed name
+    #[arg(long, action, conflicts_with_all = ["monorepo", "raw"])]
+    discover: bool,
+
+    /// Extract archives straight into the repository root, without a `map/` directory,
+    /// README or scaffolding commit. Every import deletes everything in the repository
+    /// except `.git`
+    #[arg(long, action, conflicts_with_all = ["monorepo", "template_repo"])]
+    raw: bool,
+
+    /// Keep a CHANGELOG.md in every repository with an entry per import,
+    /// listing the difficulties it changed
+    #[arg(long, action, conflicts_with = "raw")]
+    changelog: bool,
+
+    /// Tag every import that changes the map with the next version number, `v1`, `v2`, ...
+    /// (`<name>/v1` in monorepo mode). The last version is kept in `.gitosu/version`
+    #[arg(long, action, conflicts_with = "raw")]
+    version_tags: bool,
+
+    /// Track images, sounds, videos, archives and other binary files with Git LFS, keeping
+    /// the rules in `.gitattributes` up to date on every import. Needs Git LFS installed
+    #[arg(long, action, conflicts_with = "raw")]
+    smart_lfs: bool,
+
+    /// Don't commit the README and map directory of new repositories on their own,
+    /// the first import commits them together with the map
+    #[arg(long, action)]
+    no_scaffold_commit: bool,
+
+    /// Put repositories into a directory per game mode, e.g. `mania/<name>`
+    #[arg(long, action)]
+    group_by_mode: bool,
+
+    /// Which directory maps with difficulties in several modes go to with --group-by-mode
+    #[arg(long, value_enum, default_value_t = MixedModes::First, requires = "group_by_mode")]
+    mixed_modes: MixedModes,
+
+    /// Serve import metrics in the Prometheus text format on this address (watcher only)
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Log an import metrics summary every N seconds (watcher only)
+    #[arg(long, value_name = "SECONDS")]
+    metrics_interval: Option<u64>,
+
+    /// Serve `/healthz` (the watcher is running) and `/readyz` (its directories are
+    /// accessible) on this address for container health checks (watchers only)
+    #[arg(long, value_name = "ADDR")]
+    health_addr: Option<SocketAddr>,
+
+    /// Hold at most this many filesystem events the watchers haven't handled yet,
+    /// instead of any number
+    #[arg(long, value_name = "EVENTS")]
+    event_queue_size: Option<usize>,
+
+    /// What happens to new filesystem events while the --event-queue-size queue is full
+    #[arg(long, value_enum, default_value_t = EventOverflow::Block, requires = "event_queue_size")]
+    event_overflow: EventOverflow,
+
+    /// Directory copied into every new repository instead of the default README
+    /// `{map_name}` in text files is replaced with the repository name
+    #[arg(long, value_name = "PATH")]
+    template_repo: Option<PathBuf>,
+
+    /// Import into existing repositories that don't look like gitosu repositories without asking,
+    /// and skip the other confirmation questions
+    #[arg(long, action)]
+    force: bool,
+
+    /// Run even if another instance is using the repositories directory
+    #[arg(long, action)]
+    ignore_lock: bool,
+
+    /// Add Co-authored-by trailers for every mapper of collab maps
+    #[arg(long, action)]
+    collab_trailers: bool,
+
+    /// Add Gitosu-Version and Gitosu-Options trailers to import commits, recording the
+    /// gitosu version and the options that change what gets imported
+    #[arg(long, action)]
+    record_provenance: bool,
+
+    /// Record the entry order and comment of imported archives in `.gitosu/archive.json`,
+    /// committed with the map, so exports write archives laid out like the original
+    #[arg(long, action, conflicts_with = "raw")]
+    keep_layout: bool,
+
+    /// Email used in Co-authored-by trailers, `{creator}` is replaced with the mapper's name
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        default_value = "{creator}@users.noreply.github.com"
+    )]
+    collab_email: String,
+
+    /// Regex for file names the watcher ignores (temporary or partially written files)
+    #[arg(long, value_name = "REGEX", default_value = DEFAULT_IGNORE_PATTERN)]
+    ignore_pattern: String,
+
+    /// Commit every import to a branch named from the map metadata
+    /// Supports `{title}`, `{artist}`, `{creator}`, `{version}`, `{mode}`, `{beatmap_id}`
+    /// and `{beatmap_set_id}` of the difficulty --metadata-from selects, e.g. `{version}`
+    #[arg(long, value_name = "TEMPLATE")]
+    branch_template: Option<String>,
+
+    /// Always import into this branch, switching back to it (or creating it) if another
+    /// branch or an old commit is checked out
+    #[arg(long, value_name = "BRANCH", conflicts_with = "branch_template")]
+    main_branch: Option<String>,
+
+    /// Switch repositories with a detached HEAD back to their default branch
+    /// instead of refusing to import
+    #[arg(long, action)]
+    reattach_head: bool,
+
+    /// Refuse to import into repositories with uncommitted changes
+    /// instead of stashing the changes first
+    #[arg(long, action)]
+    no_overwrite_dirty: bool,
+
+    /// Only rewrite files that changed since the last import (compared by size and CRC32)
+    /// instead of clearing the map directory
+    #[arg(long, action)]
+    incremental: bool,
+
+    /// Skip archive entries that fail to extract instead of aborting the import,
+    /// the commit is marked as a partial import and lists the skipped files
+    #[arg(long, action)]
+    best_effort: bool,
+
+    /// Add timestamps to log lines
+    #[arg(long, action)]
+    log_timestamps: bool,
+
+    /// Log output format, json writes one object per line for log collectors
+    #[arg(long, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Print how long every import phase (opening the archive, repository resolution,
+    /// extraction, commit) took as a span tree on stderr, for diagnosing slow imports
+    #[arg(long, action)]
+    trace_spans: bool,
+
+    /// Instead of watching exports, watch the repositories and export every map
+    /// whose `map/` changed (e.g. after a git pull) into this directory
+    #[arg(long, value_name = "OUTPUT")]
+    watch_repos: Option<PathBuf>,
+
+    /// Only import difficulties with these names (`[Metadata] Version`), comma-separated
+    /// Assets only used by other difficulties are skipped too
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    difficulties: Option<Vec<String>>,
+
+    /// Don't import storyboards (.osb files), or the assets only they use
+    #[arg(long, action)]
+    no_storyboard: bool,
+
+    /// Don't import video files, whether difficulties use them or not
+    #[arg(long, action)]
+    no_video: bool,
+
+    /// Don't import images, audio and videos no difficulty or storyboard references.
+    /// Hitsounds and beatmap skin elements osu! uses by name are always kept
+    #[arg(long, action)]
+    prune_orphan_assets: bool,
+
+    /// Store extracted files under NFC-normalized names without trailing whitespace,
+    /// rewriting the references in .osu and .osb files to match
+    #[arg(long, action)]
+    normalize_names: bool,
+
+    /// Mark commits of imports with integrity issues in their title, e.g.
+    /// `[warning: missing audio]`, so they can be found in the history later
+    #[arg(long, action)]
+    annotate_warnings: bool,
+
+    /// Author name used when git has no identity configured
+    #[arg(long, value_name = "NAME", default_value = "gitosu")]
+    default_author_name: String,
+
+    /// Author email used when git has no identity configured
+    #[arg(long, value_name = "EMAIL", default_value = "gitosu@localhost")]
+    default_author_email: String,
+
+    /// Committer name, if it should differ from the author (e.g. a bot identity)
+    #[arg(long, value_name = "NAME")]
+    committer_name: Option<String>,
+
+    /// Committer email, if it should differ from the author
+    #[arg(long, value_name = "EMAIL")]
+    committer_email: Option<String>,
+
+    /// Time zone of commit timestamps: `local`, `utc` or an offset like `+02:00`
+    #[arg(long, value_name = "TZ", default_value = "local", value_parser = CommitTz::parse)]
+    commit_tz: CommitTz,
+
+    /// Which filesystem events trigger an import in the watcher
+    #[arg(long, value_enum, default_value_t = CommitOn::Both)]
+    commit_on: CommitOn,
+
+    /// Install the git hooks in this directory into new repositories, and run their
+    /// pre-commit hook before every import commit (e.g. to validate .osu files)
+    #[arg(long, value_name = "PATH")]
+    hooks_dir: Option<PathBuf>,
+
+    /// Sound file played after every import that commits, handy while the osu! editor
+    /// has focus. Anything that isn't a sound file is run as a program instead
+    #[arg(long, value_name = "PATH")]
+    import_sound: Option<PathBuf>,
+
+    /// Directory for temporary files (e.g. archives read from stdin), defaults to
+    /// .gitosu/tmp in the repositories directory so they are on the same filesystem
+    /// and not limited by a small /tmp
+    #[arg(long, value_name = "PATH")]
+    temp_dir: Option<PathBuf>,
+
+    /// Permissions of extracted files as an octal mode, e.g. 664 to make them group-writable
+    /// for collaborators. Only on unix, files otherwise get the process umask
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    file_mode: Option<u32>,
+
+    /// Permissions of new repository and map directories and of extracted directories
+    /// as an octal mode, e.g. 2775.
+    /// Group-writable modes also make new repositories shared with the group
+    /// (core.sharedRepository). Only on unix
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    dir_mode: Option<u32>,
+
+    /// Experimental: keep one copy of every extracted file in .gitosu/store in the
+    /// repositories directory and hard link the files in every `map/` to it,
+    /// so assets shared by many maps take up disk space once
+    #[arg(long, action)]
+    experimental_shared_store: bool,
+
+    /// Commit exports found by the watcher even if they change nothing
+    #[arg(long, action)]
+    watch_allow_empty: bool,
+
+    /// Delete every imported archive, and the older duplicates of it osu! exported before
+    /// (`Artist - Title (Mapper) (2).osz` and lower), once its import is done
+    #[arg(long, action)]
+    clean_exports: bool,
+
+    /// Resolve symlinks in the exports and repositories paths, and also watch
+    /// symlinked directories inside the exports directory (e.g. in Wine prefixes)
+    #[arg(long, action)]
+    follow_symlinks: bool,
+
+    /// Password used to decrypt password-protected archives
+    #[arg(long, env = "GITOSU_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Line endings used for extracted .osu and .osb files
+    #[arg(long, value_enum, default_value_t = LineEnding::Keep)]
+    normalize_eol: LineEnding,
+
+    /// Number of threads extracting archive entries, useful for huge maps on fast disks
+    #[arg(long, value_name = "N", default_value = "1")]
+    jobs: NonZeroUsize,
+
+    /// Commit every imported .osz to this orphan branch instead of the map's history,
+    /// keeping the archives in git without bloating the main branch
+    #[arg(long, value_name = "BRANCH")]
+    archive_branch: Option<String>,
+
+    /// Commit once this many exports of the same map were imported in the watcher,
+    /// instead of committing every export
+    #[arg(long, value_name = "N")]
+    batch_count: Option<NonZeroUsize>,
+
+    /// Commit batched exports anyway after this many seconds
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 300,
+        requires = "batch_count"
+    )]
+    batch_timeout: u64,
+
+    /// Collect exports that arrive within this many milliseconds of each other
+    /// and import them together in order of their names (e.g. a whole collection at once)
+    #[arg(long, value_name = "MS")]
+    burst_window: Option<u64>,
+
+    /// Exit after the next export was imported (or the next burst with --burst-window),
+    /// failing if the import failed
+    #[arg(long, action, conflicts_with = "watch_repos")]
+    once: bool,
+
+    /// Attach the name, size and hash of the imported .osz to every commit as a git note
+    #[arg(long, action)]
+    osz_notes: bool,
+
+    /// Create a draft GitHub release with the imported .osz attached after every commit,
+    /// failures are only logged
+    #[arg(long, action)]
+    github_release: bool,
+
+    /// GitHub repository the releases are created in, {name} is replaced with the repository name
+    /// (defaults to the origin remote)
+    #[arg(long, value_name = "OWNER/REPO", requires = "github_release")]
+    github_repo: Option<String>,
+
+    /// Token used to create GitHub releases
+    #[arg(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    github_token: Option<String>,
+
+    /// Run as a background service: shut down cleanly on SIGTERM/SIGINT,
+    /// write a PID file and log to a file when not attached to a terminal
+    #[arg(long, action)]
+    daemon: bool,
+
+    /// Replaces characters that aren't allowed in directory names on some platforms
+    /// (`<>:"/\|?*` and control characters) in repository names
+    #[arg(long, value_name = "TEXT", default_value = "_")]
+    name_substitute: String,
+
+    /// Program naming repositories: it gets the archive's metadata as JSON on stdin and
+    /// prints the repository name. Built-in naming is used if it fails or prints nothing
+    #[arg(long, value_name = "PROGRAM")]
+    name_script: Option<PathBuf>,
+
+    /// Difficulty whose metadata names repositories and fills READMEs when the
+    /// difficulties disagree: `first`, `highest-diff` or `named:<version>`
+    #[arg(long, value_name = "DIFFICULTY", default_value = "first", value_parser = MetadataFrom::parse)]
+    metadata_from: MetadataFrom,
+
+    /// PID file written in daemon mode, defaults to .gitosu/gitosu.pid in the repositories directory
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    pid_file: Option<PathBuf>,
+
+    /// Write logs to this file instead of stderr. In daemon mode without a terminal,
+    /// logs go to .gitosu/gitosu.log in the repositories directory by default
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+
     #[clap(subcommand)]
     command: Option<Commands>,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// Human readable colored output
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum CommitOn {
+    /// New files in the exports directory
+    Create,
+    /// Files renamed into the exports directory or rewritten in place
+    Modify,
+    /// Both created and renamed files
+    Both,
+}
+
+impl CommitOn {
+    fn create(self) -> bool {
+        matches!(self, Self::Create | Self::Both)
+    }
+
+    fn modify(self) -> bool {
+        matches!(self, Self::Modify | Self::Both)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum MixedModes {
+    /// The mode of the first difficulty (by file name)
+    First,
+    /// The mode most difficulties are in
+    Most,
+    /// A separate `mixed` directory
+    Mixed,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum EventOverflow {
+    /// Wait until the watcher handled an event, the OS buffers events meanwhile
+    Block,
+    /// Discard new events with a warning
+    Drop,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LineEnding {
+    /// Unix line endings
+    Lf,
+    /// Windows line endings (what osu! writes)
+    Crlf,
+    /// Leave files as they are in the archive
+    Keep,
+}
+
+impl LineEnding {
+    /// Whether files at `path` get their line endings rewritten
+    fn applies_to(self, path: &Path) -> bool {
+        self != Self::Keep
+            && path
+                .extension()
+                .map(|x| x.eq_ignore_ascii_case("osu") || x.eq_ignore_ascii_case("osb"))
+                .unwrap_or(false)
+    }
+
+    /// Rewrites every line ending in `text`
+    fn apply(self, text: &[u8]) -> Vec<u8> {
+        let mut result = Vec::with_capacity(text.len());
+        let mut iter = text.iter().peekable();
+        while let Some(&byte) = iter.next() {
+            if byte == b'\r' && iter.peek() == Some(&&b'\n') {
+                continue;
+            }
+            if byte == b'\n' && self == Self::Crlf {
+                result.push(b'\r');
+            }
+            result.push(byte);
+        }
+        result
+    }
+}
+
+/// Time zone commit timestamps are recorded in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CommitTz {
+    /// The offset of the machine gitosu runs on
+    Local,
+    /// A fixed offset from UTC in minutes
+    Fixed(i32),
+}
+
+impl CommitTz {
+    /// Parses `local`, `utc` or an offset like `+02:00`, `-0530` or `+9`
+    fn parse(value: &str) -> Result<Self, String> {
+        let error = || format!("{} is not local, utc or an offset like +02:00", value);
+        match value.to_lowercase().as_str() {
+            "local" => return Ok(Self::Local),
+            "utc" | "z" => return Ok(Self::Fixed(0)),
+            _ => {}
+        }
+        let (sign, offset) = match value.split_at_checked(1) {
+            Some(("+", offset)) => (1, offset),
+            Some(("-", offset)) => (-1, offset),
+            _ => return Err(error()),
+        };
+        let (hours, minutes) = match offset.split_once(':') {
+            Some(parts) => parts,
+            None if offset.len() > 2 => offset.split_at(offset.len() - 2),
+            None => (offset, "0"),
+        };
+        let is_number =
+            |x: &str| !x.is_empty() && x.len() <= 2 && x.bytes().all(|x| x.is_ascii_digit());
+        if !is_number(hours) || !is_number(minutes) {
+            return Err(error());
+        }
+        let (hours, minutes): (i32, i32) = (hours.parse().unwrap(), minutes.parse().unwrap());
+        if hours > 14 || minutes >= 60 {
+            return Err(error());
+        }
+        Ok(Self::Fixed(sign * (hours * 60 + minutes)))
+    }
+
+    /// `signature` at the same point in time, in this time zone
+    fn apply(self, signature: git2::Signature<'static>) -> git2::Signature<'static> {
+        let Self::Fixed(offset) = self else {
+            return signature;
+        };
+        let time = git2::Time::new(signature.when().seconds(), offset);
+        match (signature.name(), signature.email()) {
+            (Some(name), Some(email)) => git2::Signature::new(name, email, &time)
+                .map(|x| x.to_owned())
+                .unwrap_or(signature),
+            _ => signature,
+        }
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Manually import .osz file
     Import {
+        /// .osz files to import, or `-` to read a single file from stdin
+        /// (requires --use-repository)
+        #[arg(required_unless_present = "from_list")]
+        files: Vec<PathBuf>,
+
+        /// Override target repository name, only for a single file
+        #[arg(long)]
+        use_repository: Option<String>,
+
+        /// Import the .osz files listed in this file in order, one per line.
+        /// A tab after the path gives the repository to use for that file
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["files", "use_repository"])]
+        from_list: Option<PathBuf>,
+
+        /// Open the repository directory after a successful import
+        #[arg(long, action)]
+        open: bool,
+
+        /// Command used to open the repository directory (implies --open)
+        /// The directory path is passed as the last argument
+        #[arg(long, value_name = "COMMAND")]
+        open_with: Option<String>,
+
+        /// Commit even if the archive changes nothing compared to the last commit
+        #[arg(long, action)]
+        allow_empty: bool,
+    },
+    /// Export a map repository as an .osz file
+    Export {
+        /// Repository to export, defaults to the current directory
+        #[arg(conflicts_with = "all")]
+        repository: Option<String>,
+
+        /// Export every repository into `<name>.osz` files in the output directory,
+        /// skipping repositories with uncommitted changes unless --force is given
+        #[arg(long, action, conflicts_with = "at")]
+        all: bool,
+
+        /// Output file, defaults to `<repository name>.osz` in the current directory
+        /// With --all, the output directory, defaults to the current directory
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Compression method, osu! only supports store and deflate
+        #[arg(long, value_enum, default_value_t = Compression::Deflate)]
+        compression: Compression,
+
+        /// Compression level, the range depends on the method
+        #[arg(long)]
+        compression_level: Option<i64>,
+
+        /// Export the map as it was at this commit, tag or branch instead of the working tree
+        #[arg(long, value_name = "REV")]
+        at: Option<String>,
+    },
+    /// Check the environment and configuration for common problems
+    Doctor {
+        /// How the results are printed, json and csv are meant for scripts
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+    },
+    /// Print the configuration in effect and where each value came from
+    Config,
+    /// List all map repositories with their metadata and status
+    List {
+        /// How the list is printed, json and csv are meant for scripts and spreadsheets
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+    },
+    /// Check that every object in every repository is present and intact
+    Fsck {
+        /// How the results are printed, json and csv are meant for scripts
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+    },
+    /// Check that every repository is named after its committed map
+    Audit {
+        /// How the results are printed, json and csv are meant for scripts
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+    },
+    /// Regenerate the README of a map repository from its committed metadata
+    Refresh {
+        /// Repository to refresh, defaults to the current directory
+        #[arg(conflicts_with = "all")]
+        repository: Option<String>,
+
+        /// Refresh every repository
+        #[arg(long, action)]
+        all: bool,
+
+        /// Regenerate the whole README from the template, even if it has markers
+        #[arg(long, action)]
+        full: bool,
+    },
+    /// Show what changed in a map between two revisions (HEAD~1 and HEAD by default)
+    Diff {
+        /// Repository to compare
+        #[arg()]
+        repository: String,
+
+        /// Revision to compare from
+        #[arg(default_value = "HEAD~1")]
+        from: String,
+
+        /// Revision to compare to
+        #[arg(default_value = "HEAD")]
+        to: String,
+    },
+    /// Show differently named files with identical contents and the space they waste
+    Duplicates {
+        /// Repository to check, defaults to the current directory
+        #[arg(conflicts_with = "all")]
+        repository: Option<String>,
+
+        /// Check every repository
+        #[arg(long, action)]
+        all: bool,
+    },
+    /// Squash old commits into one snapshot commit per day, keeping the latest ones
+    /// Rewrites history, the old history is kept in refs/gitosu/backup/
+    Squash {
+        /// Repository to squash, defaults to the current directory
+        #[arg(conflicts_with = "all")]
+        repository: Option<String>,
+
+        /// Squash every repository
+        #[arg(long, action)]
+        all: bool,
+
+        /// Number of latest commits that are never squashed
+        #[arg(long, value_name = "N", default_value_t = 20)]
+        keep: usize,
+
+        /// Only squash commits older than this many days
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+    },
+    /// Replace the history of a map repository with a single commit of an .osz file
+    ResetFrom {
         /// .osz file to import
         #[arg()]
         file: PathBuf,
 
+        /// Override target repository name
+        #[arg(long, visible_alias = "repo")]
+        use_repository: Option<String>,
+
+        /// Move the old `.git` directory to `.git.bak` instead of deleting it
+        #[arg(long, action)]
+        backup: bool,
+    },
+    /// Print the commit message importing an .osz file would make, without importing it
+    Message {
+        /// .osz file to check
+        #[arg()]
+        file: PathBuf,
+
         /// Override target repository name
         #[arg(long)]
         use_repository: Option<String>,
     },
+    /// Print the repository name an .osz file would be imported into
+    Name {
+        /// .osz file to check
+        #[arg()]
+        file: PathBuf,
+
+        /// Override target repository name
+        #[arg(long)]
+        use_repository: Option<String>,
+
+        /// How the name is printed, json and csv are meant for scripts
+        #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+        output_format: OutputFormat,
+    },
 }
 
 #[derive(Debug)]
@@ -52,290 +793,4226 @@ struct Config {
     exports: PathBuf,
     repos: PathBuf,
     keep_latest_osz: bool,
+    keep_osz_path: String,
+    auto_gc: bool,
+    gc_threshold: usize,
+    confirm_large_repo: Option<u64>,
+    max_repos: Option<usize>,
+    monorepo: bool,
+    discover: bool,
+    raw: bool,
+    changelog: bool,
+    version_tags: bool,
+    smart_lfs: bool,
+    no_scaffold_commit: bool,
+    group_by_mode: bool,
+    mixed_modes: MixedModes,
+    metrics_addr: Option<SocketAddr>,
+    metrics_interval: Option<Duration>,
+    health_addr: Option<SocketAddr>,
+    event_queue_size: Option<usize>,
+    event_overflow: EventOverflow,
+    commit_on: CommitOn,
+    watch_allow_empty: bool,
+    clean_exports: bool,
+    follow_symlinks: bool,
+    hooks_dir: Option<PathBuf>,
+    import_sound: Option<PathBuf>,
+    temp_dir: PathBuf,
+    file_mode: Option<u32>,
+    dir_mode: Option<u32>,
+    shared_store: Option<PathBuf>,
+    template_repo: Option<PathBuf>,
+    force: bool,
+    ignore_lock: bool,
+    collab_trailers: bool,
+    record_provenance: bool,
+    keep_layout: bool,
+    collab_email: String,
+    ignore_pattern: Regex,
+    branch_template: Option<String>,
+    main_branch: Option<String>,
+    reattach_head: bool,
+    no_overwrite_dirty: bool,
+    incremental: bool,
+    best_effort: bool,
+    watch_repos: Option<PathBuf>,
+    difficulties: Option<Vec<String>>,
+    no_storyboard: bool,
+    no_video: bool,
+    prune_orphan_assets: bool,
+    normalize_names: bool,
+    annotate_warnings: bool,
+    default_author_name: String,
+    default_author_email: String,
+    committer_name: Option<String>,
+    committer_email: Option<String>,
+    commit_tz: CommitTz,
+    password: Option<String>,
+    normalize_eol: LineEnding,
+    jobs: usize,
+    archive_branch: Option<String>,
+    batch_count: Option<usize>,
+    batch_timeout: Duration,
+    burst_window: Option<Duration>,
+    once: bool,
+    osz_notes: bool,
+    name_substitute: String,
+    name_script: Option<PathBuf>,
+    metadata_from: MetadataFrom,
+    github_release: bool,
+    github_repo: Option<String>,
+    github_token: Option<String>,
 }
 
 impl Config {
     pub fn parse(args: &Args) -> anyhow::Result<Self> {
-        let exports = match &args.exports {
-            Some(p) => p.clone(),
-            None => std::env::current_dir().expect("unable to get the current working directory"),
-        };
-        let repos = match &args.repositories {
-            Some(p) => p.clone(),
-            None => std::env::current_dir().expect("unable to get the current working directory"),
-        };
+        let exports = exports_dir(args);
+        let repos = dir_or_cwd(&args.repositories);
 
-        match std::fs::exists(&exports) {
-            Ok(true) => {}
-            Ok(false) => anyhow::bail!("Exports directory doesn't exist!"),
-            Err(err) => anyhow::bail!("Failed to check exports directory: {}", err),
+        check_directory(&exports, "Exports")?;
+        check_directory(&repos, "Repositories")?;
+        // Watches and event paths then refer to the real directories
+        let (exports, repos) = match args.follow_symlinks {
+            true => (
+                exports
+                    .canonicalize()
+                    .map_err(|x| anyhow!("Failed to resolve the exports directory: {}", x))?,
+                repos
+                    .canonicalize()
+                    .map_err(|x| anyhow!("Failed to resolve the repositories directory: {}", x))?,
+            ),
+            false => (exports, repos),
         };
-        match std::fs::exists(&repos) {
-            Ok(true) => {}
-            Ok(false) => anyhow::bail!("Repositories directory doesn't exist!"),
-            Err(err) => anyhow::bail!("Failed to check repositories directory: {}", err),
+        if args.smart_lfs {
+            lfs::check_installed()?;
+        }
+        let ignore_pattern = Regex::new(&args.ignore_pattern)
+            .map_err(|x| anyhow!("Invalid ignore pattern: {}", x))?;
+        if let Err(err) =
+            git2::Signature::now(&args.default_author_name, &args.default_author_email)
+        {
+            anyhow::bail!("Invalid default author: {}", err);
+        }
+        if let Err(err) = git2::Signature::now(
+            args.committer_name
+                .as_deref()
+                .unwrap_or(&args.default_author_name),
+            args.committer_email
+                .as_deref()
+                .unwrap_or(&args.default_author_email),
+        ) {
+            anyhow::bail!("Invalid committer: {}", err);
+        }
+        if let Some(output) = &args.watch_repos {
+            check_directory(output, "Output")?;
+        }
+        // Metadata can't add directories, so sample values catch paths that are always invalid
+        let sample = metadata::Difficulty {
+            title: "title".to_string(),
+            artist: "artist".to_string(),
+            creator: "creator".to_string(),
+            version: "version".to_string(),
+            ..Default::default()
         };
+        kept_osz_path(&args.keep_osz_path, "name", &sample)?;
+        if args.name_substitute.chars().any(is_forbidden_char) {
+            anyhow::bail!("The name substitute contains characters it's supposed to replace");
+        }
+        if let Some(branch) = &args.archive_branch
+            && !git2::Branch::name_is_valid(branch).unwrap_or(false)
+        {
+            anyhow::bail!("Invalid archive branch name: {}", branch);
+        }
+        if let Some(branch) = &args.main_branch {
+            if !git2::Branch::name_is_valid(branch).unwrap_or(false) {
+                anyhow::bail!("Invalid main branch name: {}", branch);
+            }
+            if args.archive_branch.as_ref() == Some(branch) {
+                anyhow::bail!("The main branch can't be the archive branch");
+            }
+        }
+        if args.github_release && args.github_token.is_none() {
+            anyhow::bail!("--github-release needs a token, set GITHUB_TOKEN or use --github-token");
+        }
+        if let Some(template) = &args.template_repo
+            && !template.is_dir()
+        {
+            anyhow::bail!("Template repository directory doesn't exist!");
+        }
+        if let Some(hooks) = &args.hooks_dir
+            && !hooks.is_dir()
+        {
+            anyhow::bail!("Hooks directory doesn't exist!");
+        }
+        if args.event_queue_size == Some(0) {
+            anyhow::bail!("The event queue has to hold at least one event");
+        }
+
+        let temp_dir = args
+            .temp_dir
+            .clone()
+            .unwrap_or_else(|| repos.join(".gitosu").join("tmp"));
+        let shared_store = args
+            .experimental_shared_store
+            .then(|| repos.join(store::DIRECTORY));
 
-        Ok(Self { exports, repos, keep_latest_osz: args.keep_latest_osz })
+        Ok(Self {
+            exports,
+            repos,
+            keep_latest_osz: args.keep_latest_osz,
+            keep_osz_path: args.keep_osz_path.clone(),
+            auto_gc: args.auto_gc,
+            gc_threshold: args.gc_threshold,
+            confirm_large_repo: args.confirm_large_repo,
+            max_repos: args.max_repos,
+            monorepo: args.monorepo,
+            discover: args.discover,
+            raw: args.raw,
+            changelog: args.changelog,
+            version_tags: args.version_tags,
+            smart_lfs: args.smart_lfs,
+            no_scaffold_commit: args.no_scaffold_commit,
+            group_by_mode: args.group_by_mode,
+            mixed_modes: args.mixed_modes,
+            metrics_addr: args.metrics_addr,
+            metrics_interval: args.metrics_interval.map(Duration::from_secs),
+            health_addr: args.health_addr,
+            event_queue_size: args.event_queue_size,
+            event_overflow: args.event_overflow,
+            commit_on: args.commit_on,
+            watch_allow_empty: args.watch_allow_empty,
+            clean_exports: args.clean_exports,
+            follow_symlinks: args.follow_symlinks,
+            hooks_dir: args.hooks_dir.clone(),
+            import_sound: args.import_sound.clone(),
+            temp_dir,
+            file_mode: args.file_mode,
+            dir_mode: args.dir_mode,
+            shared_store,
+            template_repo: args.template_repo.clone(),
+            force: args.force,
+            ignore_lock: args.ignore_lock,
+            collab_trailers: args.collab_trailers,
+            record_provenance: args.record_provenance,
+            keep_layout: args.keep_layout,
+            collab_email: args.collab_email.clone(),
+            ignore_pattern,
+            branch_template: args.branch_template.clone(),
+            main_branch: args.main_branch.clone(),
+            reattach_head: args.reattach_head,
+            no_overwrite_dirty: args.no_overwrite_dirty,
+            incremental: args.incremental,
+            best_effort: args.best_effort,
+            watch_repos: args.watch_repos.clone(),
+            difficulties: args.difficulties.clone(),
+            no_storyboard: args.no_storyboard,
+            no_video: args.no_video,
+            prune_orphan_assets: args.prune_orphan_assets,
+            normalize_names: args.normalize_names,
+            annotate_warnings: args.annotate_warnings,
+            default_author_name: args.default_author_name.clone(),
+            default_author_email: args.default_author_email.clone(),
+            committer_name: args.committer_name.clone(),
+            committer_email: args.committer_email.clone(),
+            commit_tz: args.commit_tz,
+            password: args.password.clone(),
+            normalize_eol: args.normalize_eol,
+            jobs: args.jobs.get(),
+            archive_branch: args.archive_branch.clone(),
+            batch_count: args.batch_count.map(|x| x.get()),
+            batch_timeout: Duration::from_secs(args.batch_timeout),
+            burst_window: args.burst_window.map(Duration::from_millis),
+            once: args.once,
+            osz_notes: args.osz_notes,
+            name_substitute: args.name_substitute.clone(),
+            name_script: args.name_script.clone(),
+            metadata_from: args.metadata_from.clone(),
+            github_release: args.github_release,
+            github_repo: args.github_repo.clone(),
+            github_token: args.github_token.clone(),
+        })
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    // if let Err(_) = std::env::var("RUST_LOG") {
-    //     std::env::set_var("RUST_LOG", "info");
-    // }
-    pretty_env_logger::init();
-
-    let args = Args::parse();
-    let config = Arc::new(Config::parse(&args)?);
-
-    // TODO: export command
-
-    if let Some(command) = args.command {
-        return command.run(config.clone());
+/// Returns the given directory or the current working directory
+fn dir_or_cwd(path: &Option<PathBuf>) -> PathBuf {
+    match path {
+        Some(p) => p.clone(),
+        None => std::env::current_dir().expect("unable to get the current working directory"),
     }
+}
 
-    watcher(config.clone())
+/// The exports directory: `--exports`, the osu! installation's with `--auto-detect`,
+/// or the current working directory
+fn exports_dir(args: &Args) -> PathBuf {
+    if args.exports.is_none() && args.auto_detect {
+        match detect::exports_dir() {
+            Some(dir) => {
+                info!(
+                    "[{}] Found osu! exports in {}",
+                    "i".cyan(),
+                    dir.to_string_lossy().cyan()
+                );
+                return dir;
+            }
+            None => warn!(
+                "[{}] No osu! installation found, using the current directory",
+                "!".yellow()
+            ),
+        }
+    }
+    dir_or_cwd(&args.exports)
 }
 
-fn watcher(config: Arc<Config>) -> anyhow::Result<()> {
-    let (tx, rx) = std::sync::mpsc::channel();
-    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())?;
-    watcher.watch(&config.exports, RecursiveMode::NonRecursive)?;
+/// Checks that a directory gitosu works with exists
+fn check_directory(path: &Path, name: &str) -> anyhow::Result<()> {
+    match std::fs::exists(path) {
+        Ok(true) => Ok(()),
+        Ok(false) => anyhow::bail!("{} directory doesn't exist!", name),
+        Err(err) => anyhow::bail!("Failed to check {} directory: {}", name.to_lowercase(), err),
+    }
+}
 
-    let is_osz_path = |x: &PathBuf| {
-        x.is_file() && x.extension().map(|x| x == "osz").unwrap_or(false) && x.file_name().is_some()
+/// Sets up logging according to `--log-format` and `--log-timestamps`
+fn init_logger(args: &Args) {
+    let mut builder = match args.log_format {
+        LogFormat::Pretty if args.log_timestamps => pretty_env_logger::formatted_timed_builder(),
+        LogFormat::Pretty => pretty_env_logger::formatted_builder(),
+        LogFormat::Json => {
+            let ansi = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
+            let mut builder = pretty_env_logger::env_logger::Builder::new();
+            builder.format(move |buf, record| {
+                let message = record.args().to_string();
+                // Colors are only useful on a terminal
+                let message = ansi.replace_all(&message, "");
+                writeln!(
+                    buf,
+                    "{{\"timestamp\":\"{}\",\"level\":\"{}\",\"target\":\"{}\",\"message\":\"{}\"}}",
+                    buf.timestamp_millis(),
+                    record.level(),
+                    json_escape(record.target()),
+                    json_escape(&message)
+                )
+            });
+            builder
+        }
     };
-
-    info!(
-        "{} is now monitoring {}!",
-        "gitosu".purple(),
-        config.exports.to_string_lossy().purple()
-    );
-
-    for v in rx {
-        match v {
-            Ok(event) => {
-                trace!("{:#?}", event);
-                match event.kind {
-                    EventKind::Create(CreateKind::File) => {
-                        for path in event.paths.into_iter().filter(is_osz_path) {
-                            match import_file(&path, config.clone(), None) {
-                                Ok(_) => info!("Import completed! Don't forget to push!"),
-                                Err(err) => error!("[{}] Import failed! {}", "x".red(), err),
-                            };
-                        }
-                    }
-                    EventKind::Modify(ModifyKind::Name(mode)) => {
-                        let new_path = match mode {
-                            // notify emits duplicate events (Both and From/To)
-                            // RenameMode::Both => Some(event.paths[1].clone()),
-                            RenameMode::To => Some(event.paths[0].clone()),
-                            _ => None,
-                        };
-                        if let Some(path) = new_path {
-                            if is_osz_path(&path) {
-                                match import_file(&path, config.clone(), None) {
-                                    Ok(_) => info!("Import completed! Don't forget to push!"),
-                                    Err(err) => error!("[{}] Import failed! {}", "x".red(), err),
-                                };
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            Err(err) => {
-                error!("Error while watching exports: {}", err);
+    if args.daemon {
+        // Nobody is watching the terminal, so log imports too
+        builder.filter_level(log::LevelFilter::Info);
+    }
+    if let Some(path) = log_file_path(args) {
+        match LogFile::open(&path) {
+            Ok(file) => {
+                builder.target(pretty_env_logger::env_logger::Target::Pipe(Box::new(file)));
             }
+            Err(err) => eprintln!(
+                "Failed to open log file {}, logging to stderr: {}",
+                path.to_string_lossy(),
+                err
+            ),
         }
     }
+    builder.parse_env("RUST_LOG").init();
+}
 
-    Ok(())
+/// Prints import phase spans with their durations when they close,
+/// regular log messages keep going through `log`
+fn init_tracing() {
+    tracing_subscriber::fmt()
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr)
+        .init();
 }
 
-fn import_file(path: &PathBuf, config: Arc<Config>, override_repo: Option<String>) -> anyhow::Result<()> {
-    info!(
-        "[{}] Importing {}...",
-        "+".green(),
-        path.file_name().unwrap().to_string_lossy().green()
-    );
+fn log_file_path(args: &Args) -> Option<PathBuf> {
+    match &args.log_file {
+        Some(path) => Some(path.clone()),
+        None if args.daemon && !std::io::stderr().is_terminal() => Some(
+            dir_or_cwd(&args.repositories)
+                .join(".gitosu")
+                .join("gitosu.log"),
+        ),
+        None => None,
+    }
+}
 
-    let mut name: Option<String> = None;
+/// Log file that strips the colors from messages
+struct LogFile {
+    file: File,
+    ansi: Regex,
+}
 
-    // Default naming
-    let duplicate_regex = Regex::new(r"(.+? \(.+?\))( \((\d+)\))?\.osz").unwrap();
-    for caps in duplicate_regex.captures_iter(&path.file_name().unwrap().to_string_lossy()) {
-        if let Some(n) = caps.get(1) {
-            name = Some(n.as_str().to_string());
-            break;
+impl LogFile {
+    fn open(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
-        // Map and author
-        // info!("{:?}", caps.get(1));
-        // Duplicate number
-        // info!("{:?}", caps.get(3));
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file,
+            ansi: Regex::new(r"\x1b\[[0-9;]*m").unwrap(),
+        })
     }
+}
 
-    let name = match name {
-        Some(n) => n,
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // Every log record is written at once, so escape codes aren't split between writes
+        let text = String::from_utf8_lossy(buf);
+        self.file
+            .write_all(self.ansi.replace_all(&text, "").as_bytes())?;
+        Ok(buf.len())
+    }
 
-        // Import was ran from `gitosu commit`
-        None if override_repo.is_some() => override_repo.unwrap(),
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
 
-        // File doesn't match the default naming scheme,
-        // just use the file name without .osz
+/// Set when the daemon receives a termination signal
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the watchers should stop
+fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Daemon mode state, removes the PID file when dropped
+struct Daemon {
+    pid_file: PathBuf,
+}
+
+impl Daemon {
+    fn start(config: &Config, pid_file: Option<PathBuf>) -> anyhow::Result<Self> {
+        ctrlc::set_handler(|| {
+            info!("Received termination signal, shutting down...");
+            SHUTDOWN.store(true, Ordering::Relaxed);
+        })
+        .map_err(|x| anyhow!("Failed to set up signal handler: {}", x))?;
+
+        let pid_file = pid_file.unwrap_or_else(|| config.repos.join(".gitosu").join("gitosu.pid"));
+        if let Some(parent) = pid_file.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|x| anyhow!("Failed to create PID file directory: {}", x))?;
+        }
+        std::fs::write(&pid_file, format!("{}\n", std::process::id()))
+            .map_err(|x| anyhow!("Failed to write PID file: {}", x))?;
+        Ok(Self { pid_file })
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.pid_file);
+    }
+}
+
+/// Escapes a string for use inside a JSON string literal
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\r' => out += "\\r",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn main() -> anyhow::Result<()> {
+    // if let Err(_) = std::env::var("RUST_LOG") {
+    //     std::env::set_var("RUST_LOG", "info");
+    // }
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|x| x.exit());
+    if args.daemon && args.command.is_some() {
+        anyhow::bail!("--daemon only works with the watchers, not with commands");
+    }
+    if args.once && args.command.is_some() {
+        anyhow::bail!("--once only works with the watcher, not with commands");
+    }
+    init_logger(&args);
+    if args.trace_spans {
+        init_tracing();
+    }
+    // Doctor reports configuration problems itself instead of failing on them
+    if let Some(Commands::Doctor { output_format }) = args.command {
+        return doctor::run(&args, output_format);
+    }
+    let config = Arc::new(Config::parse(&args)?);
+    if let Some(Commands::Config) = args.command {
+        return config::run(&config, &args, &matches);
+    }
+
+    if let Some(command) = args.command {
+        return command.run(config.clone());
+    }
+
+    let _lock = InstanceLock::acquire(&config)?;
+    let _daemon = match args.daemon {
+        true => Some(Daemon::start(&config, args.pid_file.clone())?),
+        false => None,
+    };
+    if let Some(output) = &config.watch_repos {
+        return repo_watcher::watch_repos(config.clone(), output);
+    }
+    watcher(config.clone())
+}
+
+/// Window in which rename events for the same path are considered duplicates
+const RENAME_DEDUP_WINDOW: Duration = Duration::from_secs(2);
+
+/// How long an archive rewritten in place has to go without writes before it's imported,
+/// so it isn't read while the exporter is still writing it
+const REWRITE_SETTLE: Duration = Duration::from_secs(1);
+
+/// The repository (or monorepo map directory) under `repos` that `dir` is in, if any
+///
+/// `repos` itself doesn't count, exports and repositories can share a directory
+fn containing_repository(dir: &Path, repos: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    let relative = dir.strip_prefix(repos).ok()?;
+    let mut current = repos.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        if current.join(".git").exists() || current.join("map").is_dir() {
+            return Some(current);
+        }
+    }
+    None
+}
+
+/// Whether the watcher imports the file at `path`: an existing archive that doesn't
+/// match `--ignore-pattern` and isn't inside a repository under `repos` (canonical)
+fn is_export_path(path: &Path, config: &Config, repos: &Path) -> bool {
+    // osu!lazer exports `.olz` archives for lazer itself, with the same layout
+    let is_export_extension = |x: &OsStr| x == "osz" || x == "olz";
+    let ignored = match path.file_name() {
+        Some(name) => config.ignore_pattern.is_match(&name.to_string_lossy()),
+        None => true,
+    };
+    if ignored {
+        debug!("ignoring {}", path.to_string_lossy());
+    }
+    // Archives kept with --keep-latest-osz would be imported again every time
+    // they're written
+    let in_repository = path
+        .parent()
+        .and_then(|dir| containing_repository(dir, repos))
+        .is_some();
+    if in_repository {
+        debug!(
+            "ignoring {}, it's inside a repository",
+            path.to_string_lossy()
+        );
+    }
+    path.is_file()
+        && path.extension().is_some_and(is_export_extension)
+        && !ignored
+        && !in_repository
+}
+
+/// How often the watcher checks that the exports directory still exists
+const EXPORTS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+fn watcher(config: Arc<Config>) -> anyhow::Result<()> {
+    let (tx, rx) = event_queue::channel(config.event_queue_size, config.event_overflow);
+    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())?;
+    watch_exports(&mut watcher, &config)?;
+
+    if let Some(addr) = config.metrics_addr {
+        metrics::spawn_server(addr)?;
+    }
+    if let Some(addr) = config.health_addr {
+        health::spawn_server(addr, vec![config.exports.clone(), config.repos.clone()])?;
+    }
+    if let Some(interval) = config.metrics_interval {
+        metrics::spawn_summary_logger(interval);
+    }
+
+    let repos = config
+        .repos
+        .canonicalize()
+        .unwrap_or_else(|_| config.repos.clone());
+    if let Some(repo) = containing_repository(&config.exports, &repos) {
+        warn!(
+            "[{}] The exports directory is inside the repository {}, archives in it are ignored!",
+            "!".yellow(),
+            repo.to_string_lossy().yellow()
+        );
+    }
+
+    let is_osz_path = |x: &PathBuf| is_export_path(x, &config, &repos);
+
+    info!(
+        "{} is now monitoring {}!",
+        "gitosu".purple(),
+        config.exports.to_string_lossy().purple()
+    );
+
+    let mut recent_renames: HashMap<PathBuf, Instant> = HashMap::new();
+    // Archives being rewritten in place and when they were last written to
+    let mut rewrites: HashMap<PathBuf, Instant> = HashMap::new();
+    // Size and modification time of every archive after it was last imported, so writes
+    // that were part of creating a file that was imported already don't import it again
+    let mut versions: HashMap<PathBuf, Option<(u64, SystemTime)>> = HashMap::new();
+    let mut batches: HashMap<PathBuf, Batch> = HashMap::new();
+    // Exports collected with `--burst-window`, sorted by path
+    let mut burst: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut burst_last = Instant::now();
+    let timeout = config
+        .burst_window
+        .map_or(EXPORTS_CHECK_INTERVAL, |x| x.min(EXPORTS_CHECK_INTERVAL));
+    // Watches are tied to the directory itself, so they stop working if it's deleted
+    // (e.g. osu! is moved or reinstalled) and have to be set up again when it's recreated
+    let mut watching = true;
+    // Whether the last import succeeded, the watcher stops after the first one with `--once`
+    let mut imported: Option<bool> = None;
+
+    loop {
+        health::beat();
+        let v = match rx.recv_timeout(timeout) {
+            Ok(v) => Some(v),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if shutdown_requested() {
+            import_burst(std::mem::take(&mut burst), &config, &mut batches);
+            // Don't leave batched exports uncommitted
+            for (_, batch) in batches.drain() {
+                commit_batch(&config, batch);
+            }
+            break;
+        }
+        if watching && !config.exports.is_dir() {
+            unwatch_exports(&mut watcher, &config.exports);
+            watching = false;
+        }
+        if !watching && config.exports.is_dir() {
+            match watch_exports(&mut watcher, &config) {
+                Ok(_) => {
+                    info!(
+                        "{} is monitoring {} again!",
+                        "gitosu".purple(),
+                        config.exports.to_string_lossy().purple()
+                    );
+                    watching = true;
+                }
+                Err(err) => debug!("failed to watch exports again: {}", err),
+            }
+        }
+
+        let expired: Vec<PathBuf> = batches
+            .iter()
+            .filter(|(_, batch)| batch.started.elapsed() >= config.batch_timeout)
+            .map(|(repo_path, _)| repo_path.clone())
+            .collect();
+        for repo_path in expired {
+            if let Some(batch) = batches.remove(&repo_path) {
+                commit_batch(&config, batch);
+            }
+        }
+
+        match v {
+            None => {}
+            Some(Ok(event)) => {
+                trace!("{:#?}", event);
+                match event.kind {
+                    // The directory may be recreated before the next check
+                    EventKind::Remove(_) if watching && event.paths.contains(&config.exports) => {
+                        unwatch_exports(&mut watcher, &config.exports);
+                        watching = false;
+                    }
+                    EventKind::Create(CreateKind::File) if config.commit_on.create() => {
+                        for path in event.paths.into_iter().filter(is_osz_path) {
+                            match config.burst_window {
+                                Some(_) => {
+                                    burst.insert(path);
+                                    burst_last = Instant::now();
+                                }
+                                None => {
+                                    imported = Some(watcher_import(&path, &config, &mut batches));
+                                    versions.insert(path.clone(), file_version(&path));
+                                    if config.once {
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    EventKind::Modify(ModifyKind::Name(mode)) if config.commit_on.modify() => {
+                        let new_path = match mode {
+                            // Some platforms emit both Both and From/To for a single rename,
+                            // others only emit Both, so handle both and dedup by path
+                            RenameMode::Both => event.paths.last().cloned(),
+                            RenameMode::To => event.paths.first().cloned(),
+                            _ => None,
+                        };
+                        if let Some(path) = new_path
+                            && is_osz_path(&path)
+                        {
+                            let now = Instant::now();
+                            recent_renames
+                                .retain(|_, at| now.duration_since(*at) < RENAME_DEDUP_WINDOW);
+                            let duplicate = recent_renames.contains_key(&path);
+                            // No `continue`, the checks below the match run for every event
+                            if config.burst_window.is_some() {
+                                // Bursts are deduplicated by path already
+                                burst.insert(path);
+                                burst_last = now;
+                            } else if duplicate {
+                                debug!(
+                                    "skipping duplicate rename event for {}",
+                                    path.to_string_lossy()
+                                );
+                            } else {
+                                imported = Some(watcher_import(&path, &config, &mut batches));
+                                versions.insert(path.clone(), file_version(&path));
+                                // Duplicate events are queued while importing,
+                                // so the window starts after the import is done
+                                recent_renames.insert(path, Instant::now());
+                            }
+                        }
+                    }
+                    // Some exporters overwrite an existing archive instead of creating a new one
+                    EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any)
+                        if config.commit_on.modify() =>
+                    {
+                        for path in event.paths.into_iter().filter(is_osz_path) {
+                            rewrites.insert(path, Instant::now());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some(Err(err)) => {
+                error!("Error while watching exports: {}", err);
+            }
+        }
+
+        let settled: Vec<PathBuf> = rewrites
+            .iter()
+            .filter(|(_, written)| written.elapsed() >= REWRITE_SETTLE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            rewrites.remove(&path);
+            let version = file_version(&path);
+            if versions.get(&path) == Some(&version) || !is_osz_path(&path) {
+                continue;
+            }
+            debug!("{} was rewritten", path.to_string_lossy());
+            match config.burst_window {
+                Some(_) => {
+                    burst.insert(path);
+                    burst_last = Instant::now();
+                }
+                None => {
+                    imported = Some(watcher_import(&path, &config, &mut batches));
+                    versions.insert(path.clone(), file_version(&path));
+                    if config.once {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(window) = config.burst_window
+            && !burst.is_empty()
+            && burst_last.elapsed() >= window
+        {
+            let paths = std::mem::take(&mut burst);
+            imported = Some(import_burst(paths.clone(), &config, &mut batches));
+            for path in paths {
+                versions.insert(path.clone(), file_version(&path));
+            }
+        }
+
+        if config.once
+            && let Some(succeeded) = imported
+        {
+            for (_, batch) in batches.drain() {
+                commit_batch(&config, batch);
+            }
+            if !succeeded {
+                anyhow::bail!("Import failed");
+            }
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Size and modification time of a file, `None` if it can't be read
+fn file_version(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Imports exports collected with `--burst-window` one by one, in order of their paths
+///
+/// Returns whether all imports succeeded
+fn import_burst(
+    paths: BTreeSet<PathBuf>,
+    config: &Arc<Config>,
+    batches: &mut HashMap<PathBuf, Batch>,
+) -> bool {
+    if paths.len() <= 1 {
+        return paths
+            .iter()
+            .all(|path| watcher_import(path, config, batches));
+    }
+
+    let total = paths.len();
+    info!("[{}] Importing {} exports...", "i".cyan(), total);
+    let mut failed = 0;
+    for (i, path) in paths.iter().enumerate() {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        info!(
+            "[{}] ({}/{}) {}",
+            "i".cyan(),
+            i + 1,
+            total,
+            file_name.cyan()
+        );
+        if !watcher_import(path, config, batches) {
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => info!("Imported all {} exports!", total),
+        n => warn!(
+            "[{}] Imported {} of {} exports, {} failed",
+            "!".yellow(),
+            total - n,
+            total,
+            n
+        ),
+    }
+    failed == 0
+}
+
+/// Exports of a map imported by the watcher but not committed yet (`--batch-count`)
+struct Batch {
+    staged: StagedImport,
+    /// Latest imported archive
+    osz: PathBuf,
+    count: usize,
+    started: Instant,
+}
+
+/// Imports a file found by the watcher, only committing full batches with `--batch-count`
+///
+/// Returns whether the import succeeded
+fn watcher_import(
+    path: &PathBuf,
+    config: &Arc<Config>,
+    batches: &mut HashMap<PathBuf, Batch>,
+) -> bool {
+    let Some(batch_count) = config.batch_count else {
+        return import_and_report(path, config.clone(), None, config.watch_allow_empty).is_some();
+    };
+    let Some(staged) = report_import(stage_import(path, config, None)) else {
+        return false;
+    };
+
+    let key = staged.repo_path.clone();
+    let batch = match batches.entry(key.clone()) {
+        Entry::Occupied(entry) => {
+            let batch = entry.into_mut();
+            // Only the map directory of the latest staged export ends up in the commit
+            batch.staged = staged;
+            batch
+        }
+        Entry::Vacant(entry) => entry.insert(Batch {
+            staged,
+            osz: path.clone(),
+            count: 0,
+            started: Instant::now(),
+        }),
+    };
+    batch.osz = path.clone();
+    batch.count += 1;
+    info!(
+        "[{}] Staged export {}/{} of {}",
+        "i".cyan(),
+        batch.count,
+        batch_count,
+        batch.staged.name.cyan()
+    );
+    if batch.count >= batch_count
+        && let Some(batch) = batches.remove(&key)
+    {
+        commit_batch(config, batch);
+    }
+    true
+}
+
+fn commit_batch(config: &Config, batch: Batch) {
+    let result = commit_import(config, &batch.staged, &batch.osz, config.watch_allow_empty);
+    if result.is_ok() && config.clean_exports {
+        clean_exports(&batch.osz);
+    }
+    match result {
+        Ok(true) => info!(
+            "Committed {} export(s) of {}! Don't forget to push!",
+            batch.count,
+            batch.staged.name.green()
+        ),
+        Ok(false) => info!(
+            "{} is already up to date, nothing to commit",
+            batch.staged.name.green()
+        ),
+        Err(err) => error!(
+            "[{}] Commit of {} failed! {}",
+            "x".red(),
+            batch.staged.name,
+            err
+        ),
+    }
+}
+
+/// Watches the exports directory and, with `--follow-symlinks`, the directories linked in it
+///
+/// Watches aren't recursive, and notify only follows symlinks in recursive watches,
+/// so linked directories are resolved and watched on their own
+fn watch_exports(watcher: &mut RecommendedWatcher, config: &Config) -> notify::Result<()> {
+    watcher.watch(&config.exports, RecursiveMode::NonRecursive)?;
+    if !config.follow_symlinks {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(&config.exports)?.flatten() {
+        let is_link = entry.file_type().is_ok_and(|x| x.is_symlink());
+        let Ok(target) = entry.path().canonicalize() else {
+            continue;
+        };
+        // Linking the repositories directory here would import kept archives again
+        if !is_link || !target.is_dir() || target == config.repos {
+            continue;
+        }
+        match watcher.watch(&target, RecursiveMode::NonRecursive) {
+            Ok(_) => info!(
+                "[{}] Also watching linked directory {}",
+                "i".cyan(),
+                target.to_string_lossy().cyan()
+            ),
+            Err(err) => warn!(
+                "[{}] Failed to watch linked directory {}: {}",
+                "!".yellow(),
+                target.to_string_lossy(),
+                err
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn unwatch_exports(watcher: &mut RecommendedWatcher, exports: &Path) {
+    warn!(
+        "[{}] Exports directory {} disappeared, waiting for it to come back...",
+        "!".yellow(),
+        exports.to_string_lossy()
+    );
+    let _ = watcher.unwatch(exports);
+}
+
+/// Error returned by [`import_file`] when the archive contains nothing to import
+#[derive(Debug)]
+struct EmptyArchive {
+    /// Whether the archive has entries, but all of them are directories
+    only_directories: bool,
+}
+
+impl std::fmt::Display for EmptyArchive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.only_directories {
+            true => write!(f, "Exported archive only contains directories, no files!!!"),
+            false => write!(f, "Exported archive is empty!!!"),
+        }
+    }
+}
+
+impl std::error::Error for EmptyArchive {}
+
+/// Imports a file, logging and counting the result
+fn import_and_report(
+    path: &PathBuf,
+    config: Arc<Config>,
+    override_repo: Option<String>,
+    allow_empty: bool,
+) -> Option<Imported> {
+    let imported = report_import(import_file(path, config, override_repo, allow_empty))?;
+    match imported.changed {
+        true => info!("Import completed! Don't forget to push!"),
+        false => info!("Map is already up to date, nothing to commit"),
+    }
+    Some(imported)
+}
+
+/// Reads the `--from-list` file at `path`: one archive per line, optionally followed
+/// by a tab and the repository to import it into
+///
+/// Blank lines and lines starting with `#` are skipped. Relative paths are relative
+/// to the directory of the list, so lists work from anywhere
+fn read_import_list(path: &Path) -> anyhow::Result<Vec<(PathBuf, Option<String>)>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|x| anyhow!("Failed to read {}: {}", path.to_string_lossy(), x))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut files = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let (file, repository) = match line.split_once('\t') {
+            Some((file, repository)) => (file, Some(repository.trim())),
+            None => (line, None),
+        };
+        let file = file.trim();
+        if file == "-" {
+            anyhow::bail!("Line {} of the list: stdin (`-`) can't be imported", i + 1);
+        }
+        if repository == Some("") {
+            anyhow::bail!("Line {} of the list: repository is empty", i + 1);
+        }
+        files.push((base.join(file), repository.map(str::to_string)));
+    }
+    if files.is_empty() {
+        anyhow::bail!("{} lists no files", path.to_string_lossy());
+    }
+    Ok(files)
+}
+
+/// Imports several files one after another, each with its optional repository
+/// override, printing a summary of the results
+fn import_many(
+    files: &[(PathBuf, Option<String>)],
+    config: Arc<Config>,
+    open: bool,
+    open_with: Option<&str>,
+    allow_empty: bool,
+) -> anyhow::Result<()> {
+    let mut results = vec![];
+    for (file, override_repo) in files {
+        let imported = match file.is_file() {
+            true => import_and_report(file, config.clone(), override_repo.clone(), allow_empty),
+            false => {
+                error!("[{}] {} not found!", "x".red(), file.to_string_lossy());
+                None
+            }
+        };
+        if let Some(imported) = &imported
+            && (open || open_with.is_some())
+            && let Err(err) = open_directory(&imported.repo_path, open_with)
+        {
+            warn!("[{}] Failed to open repository: {}", "!".yellow(), err);
+        }
+        results.push((file, imported));
+    }
+
+    let mut failed = 0;
+    for (file, imported) in &results {
+        let file = file.to_string_lossy();
+        match imported {
+            Some(imported) if imported.changed => println!(
+                "[{}] {} -> {}",
+                "+".green(),
+                file,
+                imported.repo_path.to_string_lossy()
+            ),
+            Some(imported) => println!(
+                "[{}] {} -> {} (already up to date)",
+                "=".cyan(),
+                file,
+                imported.repo_path.to_string_lossy()
+            ),
+            None => {
+                failed += 1;
+                println!("[{}] {} failed", "x".red(), file);
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} imports failed", failed, results.len());
+    }
+    Ok(())
+}
+
+/// Counts the result of an import, logging failures
+fn report_import<T>(result: anyhow::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => {
+            metrics::inc(&metrics::IMPORTS_SUCCEEDED);
+            Some(value)
+        }
+        Err(err) => {
+            if err.is::<EmptyArchive>() {
+                metrics::inc(&metrics::IMPORTS_SKIPPED_EMPTY);
+            } else {
+                metrics::inc(&metrics::IMPORTS_FAILED);
+            }
+            error!("[{}] Import failed! {}", "x".red(), err);
+            None
+        }
+    }
+}
+
+/// Determines the repository name for an archive
+fn resolve_repo_name(
+    path: &Path,
+    override_repo: Option<String>,
+    zip: Option<&mut ZipArchive<File>>,
+    config: &Config,
+) -> String {
+    let mut zip = zip;
+    let raw = raw_repo_name(path, override_repo, || {
+        zip.as_deref_mut()
+            .and_then(|zip| metadata_repo_name(zip, config))
+    });
+    let name = sanitize_name(&raw, &config.name_substitute);
+    if let Some(script) = &config.name_script {
+        let difficulties = zip
+            .and_then(|zip| {
+                metadata::read_archive_difficulties(zip, config.password.as_deref()).ok()
+            })
+            .unwrap_or_default();
+        match name_script::run(script, path, &name, &difficulties) {
+            Ok(named) => {
+                info!("[{}] Named by --name-script", "i".cyan());
+                return sanitize_name(&named, &config.name_substitute);
+            }
+            Err(err) => warn!(
+                "[{}] --name-script failed, using the built-in name: {}",
+                "!".yellow(),
+                err
+            ),
+        }
+    }
+    // Repositories created before names were sanitized keep being used
+    let mut components = Path::new(&raw).components();
+    if name != raw
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !config.repos.join(&name).exists()
+        && config.repos.join(&raw).is_dir()
+    {
+        return raw;
+    }
+    name
+}
+
+/// Name of the repository an archive is imported into, and its directory relative to the
+/// repositories directory with forward slashes for pathspecs (e.g. `mania/<name>`)
+fn resolve_repo(
+    path: &Path,
+    override_repo: Option<String>,
+    zip: &mut ZipArchive<File>,
+    config: &Config,
+) -> anyhow::Result<(String, String)> {
+    // Archives with generic names are named after their metadata
+    let name = resolve_repo_name(path, override_repo, Some(&mut *zip), config);
+    let relative = match config.group_by_mode {
+        true => format!("{}/{}", mode_directory(zip, config)?, name),
+        false => name.clone(),
+    };
+    // Hand-arranged collections name and nest their repositories however they like
+    if config.discover && !config.repos.join(&relative).exists() {
+        let difficulties = metadata::read_archive_difficulties(zip, config.password.as_deref())?;
+        if let Some(existing) = repos::find_existing(config, &difficulties) {
+            info!("[{}] Found an existing repository for this map", "i".cyan());
+            let name = existing.rsplit('/').next().unwrap_or(&existing).to_string();
+            return Ok((name, existing));
+        }
+    }
+    Ok((name, relative))
+}
+
+/// osu!'s export names, `Artist - Title (Mapper)` followed by a duplicate number
+/// like ` (2)` if an export with that name already existed
+const DUPLICATE_PATTERN: &str = r"(.+? \(.+?\))( \((\d+)\))?\.(osz|olz)";
+
+/// Deletes the archive at `path` and the older duplicates of it in the same directory,
+/// ones with the same name and a lower duplicate number (or none)
+fn clean_exports(path: &Path) {
+    let regex = Regex::new(&format!("^{}$", DUPLICATE_PATTERN)).unwrap();
+    let number = |caps: &regex::Captures| -> u64 {
+        caps.get(3)
+            .and_then(|x| x.as_str().parse().ok())
+            .unwrap_or(0)
+    };
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut removed = vec![path.to_path_buf()];
+    if let Some(caps) = regex.captures(&file_name)
+        && let Some(dir) = path.parent()
+        && let Ok(entries) = std::fs::read_dir(dir)
+    {
+        let (base, latest) = (caps[1].to_string(), number(&caps));
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if let Some(caps) = regex.captures(&name)
+                && caps[1] == base
+                && number(&caps) < latest
+                && entry.file_type().is_ok_and(|x| x.is_file())
+            {
+                removed.push(entry.path());
+            }
+        }
+    }
+    for path in removed {
+        match std::fs::remove_file(&path) {
+            Ok(()) => info!(
+                "[{}] Deleted {}",
+                "i".cyan(),
+                path.file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .cyan()
+            ),
+            Err(err) => warn!(
+                "[{}] Failed to delete {}: {}",
+                "!".yellow(),
+                path.to_string_lossy(),
+                err
+            ),
+        }
+    }
+}
+
+/// Repository name from the file name, `metadata` is used for names that don't
+/// follow osu!'s naming scheme and weren't overridden
+fn raw_repo_name(
+    path: &Path,
+    override_repo: Option<String>,
+    metadata: impl FnOnce() -> Option<String>,
+) -> String {
+    let mut name: Option<String> = None;
+
+    // Default naming
+    let duplicate_regex = Regex::new(DUPLICATE_PATTERN).unwrap();
+    for caps in duplicate_regex.captures_iter(&path.file_name().unwrap().to_string_lossy()) {
+        if let Some(n) = caps.get(1) {
+            name = Some(n.as_str().to_string());
+            break;
+        }
+        // Map and author
+        // info!("{:?}", caps.get(1));
+        // Duplicate number
+        // info!("{:?}", caps.get(3));
+    }
+
+    match name {
+        Some(n) => n,
+
+        // Import was ran from `gitosu commit`
+        None if override_repo.is_some() => override_repo.unwrap(),
+
+        // File doesn't match the default naming scheme (e.g. `beatmap.osz`),
+        // name it like osu! would from the map's metadata
+        None => match metadata() {
+            Some(n) => {
+                info!(
+                    "[{}] Naming repository after the map's metadata",
+                    "i".cyan()
+                );
+                n
+            }
+            // Otherwise just use the file name without .osz
+            None => {
+                let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+                match file_name
+                    .strip_suffix(".osz")
+                    .or_else(|| file_name.strip_suffix(".olz"))
+                {
+                    Some(stem) => stem.to_string(),
+                    None => file_name,
+                }
+            }
+        },
+    }
+}
+
+/// Repository name from the metadata of the archive's difficulties,
+/// see [`metadata::osz_name`]
+fn metadata_repo_name(zip: &mut ZipArchive<File>, config: &Config) -> Option<String> {
+    let difficulties = metadata::read_archive_difficulties(zip, config.password.as_deref()).ok()?;
+    if let MetadataFrom::Named(version) = &config.metadata_from
+        && !difficulties.is_empty()
+        && !difficulties
+            .iter()
+            .any(|x| x.version.eq_ignore_ascii_case(version))
+    {
+        warn!(
+            "[{}] No difficulty is named {}, using the metadata of the first one",
+            "!".yellow(),
+            version.yellow()
+        );
+    }
+    metadata::osz_name(&difficulties, &config.metadata_from)
+}
+
+/// Characters Windows doesn't allow in file names, and control characters
+fn is_forbidden_char(c: char) -> bool {
+    c.is_control() || "<>:\"/\\|?*".contains(c)
+}
+
+/// Makes a repository name usable as a directory name on every platform,
+/// replacing characters Windows forbids and control characters with `substitute`
+fn sanitize_name(name: &str, substitute: &str) -> String {
+    let mut clean = String::with_capacity(name.len());
+    for c in name.chars() {
+        match is_forbidden_char(c) {
+            true => clean += substitute,
+            false => clean.push(c),
+        }
+    }
+    // Windows strips trailing dots and spaces, which also rules out `.` and `..`
+    let mut clean = clean.trim_end_matches(['.', ' ']).to_string();
+
+    let fallback = match substitute.is_empty() {
+        true => "_",
+        false => substitute,
+    };
+    if clean.is_empty() {
+        return fallback.to_string();
+    }
+    if archive::is_reserved_name(&clean) {
+        clean.insert_str(0, fallback);
+    }
+    clean
+}
+
+/// Imports an archive into its repository and commits it
+///
+/// With `allow_empty`, archives that change nothing are committed anyway
+fn import_file(
+    path: &PathBuf,
+    config: Arc<Config>,
+    override_repo: Option<String>,
+    allow_empty: bool,
+) -> anyhow::Result<Imported> {
+    let _span =
+        info_span!("import", file = %path.file_name().unwrap_or_default().display()).entered();
+    let staged = stage_import(path, &config, override_repo)?;
+    let changed = commit_import(&config, &staged, path, allow_empty)?;
+    // Archives read from stdin are temporary files, removed on their own
+    if config.clean_exports && !path.starts_with(&config.temp_dir) {
+        clean_exports(path);
+    }
+    Ok(Imported {
+        repo_path: staged.repo_path,
+        changed,
+    })
+}
+
+/// A finished import
+struct Imported {
+    repo_path: PathBuf,
+    /// Whether anything was committed, re-importing the same archive changes nothing
+    changed: bool,
+}
+
+/// A map extracted and staged in its repository, but not committed yet
+struct StagedImport {
+    name: String,
+    repo_path: PathBuf,
+    /// What's staged, the map's directory in monorepo mode
+    pathspec: String,
+    /// Archive entries skipped with `--best-effort`
+    skipped: Vec<String>,
+    /// Held until the import is committed
+    _lock: RepoLock,
+}
+
+/// Imports an archive into its repository and stages the changes
+fn stage_import(
+    path: &PathBuf,
+    config: &Config,
+    override_repo: Option<String>,
+) -> anyhow::Result<StagedImport> {
+    info!(
+        "[{}] Importing {}...",
+        "+".green(),
+        path.file_name().unwrap().to_string_lossy().green()
+    );
+
+    // The archive is checked before anything is created, so a broken export never
+    // leaves an empty repository behind
+    let open_span = info_span!("open_archive", repo = field::Empty).entered();
+    let file = File::open(path).map_err(|x| anyhow!("Failed to open .osz: {}", x))?;
+    let mut zip = ZipArchive::new(file)
+        .map_err(|x| anyhow!("Failed to open .osz as a zip archive: {}", x))?;
+    // Directory entries alone would result in an empty map
+    if !archive::has_files(&mut zip) {
+        return Err(EmptyArchive {
+            only_directories: !zip.is_empty(),
+        }
+        .into());
+    }
+    let password = config.password.as_deref();
+    archive::check_encryption(&mut zip, password)?;
+    if config.normalize_names {
+        normalize::check_collisions(zip.file_names())?;
+    }
+    let (name, relative) = resolve_repo(path, override_repo, &mut zip, config)?;
+    open_span.record("repo", field::display(&name));
+    open_span.exit();
+
+    let resolve_span = info_span!("resolve_repo", repo = %relative).entered();
+    info!("[{}] Using map repository {}", "i".cyan(), relative.cyan());
+    // Taken before the map is cleared, imports into the same repository (or into the
+    // monorepo) from other threads wait until this one is committed
+    let lock = RepoLock::acquire(&match config.monorepo {
+        true => config.repos.clone(),
+        false => config.repos.join(&relative),
+    });
+    if let Some(max) = config.max_repos
+        && !config.repos.join(&relative).exists()
+    {
+        check_repo_limit(config, max)?;
+    }
+
+    // In monorepo mode every map lives in a subdirectory of a single repository,
+    // and all git operations are scoped to that subdirectory
+    // `created` is whether this import created the directory, so it can be removed on failure
+    let (repo, repo_path, pathspec, needs_scaffold, created) = if config.monorepo {
+        let repo = open_or_init_monorepo(&config.repos, config)?;
+        let repo_path = config.repos.join(&relative);
+        let map_exists = match std::fs::exists(&repo_path) {
+            Ok(v) => v,
+            Err(err) => anyhow::bail!("Failed to check if map directory exists: {}", err),
+        };
+        (repo, repo_path, relative.clone(), !map_exists, !map_exists)
+    } else {
+        let repo_path = config.repos.join(&relative);
+        let (repo, created) = open_or_init_repo(&repo_path, config)?;
+        // An earlier import failed before the first commit
+        let unfinished = !created && repo.is_empty().unwrap_or(false);
+        if unfinished {
+            warn!(
+                "[{}] {} was never fully initialized, finishing it",
+                "!".yellow(),
+                repo_path.to_string_lossy().yellow()
+            );
+        }
+        (
+            repo,
+            repo_path,
+            ".".to_string(),
+            // Raw repositories contain nothing but the map
+            (created || unfinished) && !config.raw,
+            created,
+        )
+    };
+
+    // Raw repositories have no map directory, they're marked when they're created instead
+    let managed = match config.raw {
+        true => repos::is_raw(&repo_path),
+        false => repo_path.join("map").is_dir(),
+    };
+    if !needs_scaffold && !created && !managed {
+        // Existing repository without a map directory wasn't created by gitosu,
+        // importing into it would commit (and overwrite) whatever is in there,
+        // raw imports even delete everything else
+        warn!(
+            "[{}] {} exists but doesn't look like a gitosu repository!",
+            "!".yellow(),
+            repo_path.to_string_lossy().yellow()
+        );
+        if !config.force && !confirm("Import into it anyway?") {
+            anyhow::bail!(
+                "Refusing to import into an unmanaged repository (use --force to override)"
+            );
+        }
+    }
+    if config.raw {
+        repos::mark_raw(&repo)?;
+    }
+
+    // `--main-branch` switches away from detached HEADs and the archive branch below
+    if config.main_branch.is_none() && repo.head_detached().unwrap_or(false) {
+        if !config.reattach_head {
+            anyhow::bail!(
+                "HEAD of {} is detached (an old commit is checked out)! Check out a branch or use --reattach-head",
+                repo_path.to_string_lossy()
+            );
+        }
+        git_reattach_head(&repo)?;
+    }
+    if let Some(branch) = &config.archive_branch
+        && config.main_branch.is_none()
+        && repo
+            .head()
+            .is_ok_and(|x| x.shorthand() == Some(branch.as_str()))
+    {
+        anyhow::bail!(
+            "The archive branch {} is checked out in {}! Check out the map's branch",
+            branch,
+            repo_path.to_string_lossy()
+        );
+    }
+
+    if needs_scaffold && let Err(err) = scaffold_repo(&repo, &repo_path, &name, &pathspec, config) {
+        // Don't leave a half initialized repository for the next import to trip over
+        if created {
+            warn!(
+                "[{}] Removing partially initialized {}",
+                "!".yellow(),
+                repo_path.to_string_lossy()
+            );
+            if let Err(err) = std::fs::remove_dir_all(&repo_path) {
+                error!("[{}] Failed to clean up: {}", "x".red(), err);
+            }
+        }
+        return Err(err);
+    }
+    resolve_span.exit();
+
+    let extract_span = info_span!("extract", repo = %relative).entered();
+    // Importing replaces the map, manual edits would be lost without a trace
+    if !needs_scaffold {
+        stash_manual_changes(&repo, &repo_path, &pathspec, config)?;
+    }
+    if let Some(template) = &config.branch_template {
+        let difficulties = metadata::read_archive_difficulties(&mut zip, password)?;
+        let selected = config.metadata_from.select(&difficulties).ok_or(anyhow!(
+            "Archive has no difficulties to fill the branch template"
+        ))?;
+        let branch = branch_name(&selected.format(template))?;
+        git_switch_branch(&repo, &branch)?;
+    }
+    // After stashing, so manual changes to the map don't block switching
+    if let Some(branch) = &config.main_branch {
+        git_checkout_main_branch(&repo, branch)?;
+    }
+
+    let excluded = match config.difficulties.is_some()
+        || config.no_storyboard
+        || config.no_video
+        || config.prune_orphan_assets
+    {
+        true => excluded_entries(&mut zip, config)?,
+        false => HashSet::new(),
+    };
+
+    let map_path = map_directory(&repo_path, config);
+    // Removing everything in the map directory
+    // (the reason why you shouldn't touch it)
+    // Incremental imports remove stale files after extracting instead
+    if !config.incremental && config.raw {
+        clear_repository(&repo_path)
+            .map_err(|x| anyhow!("Failed to clear the repository: {}", x))?;
+    } else if !config.incremental
+        && let Ok(true) = std::fs::exists(&map_path)
+    {
+        std::fs::remove_dir_all(&map_path)
+            .map_err(|x| anyhow!("Failed to clear the map directory: {}", x))?;
+    }
+    // Copy latest files into the map directory
+    info!("[{}] Importing files...", "i".cyan());
+    let (extracted, mut skipped) = match config.jobs {
+        1 => {
+            let mut extracted = HashSet::new();
+            let mut skipped = vec![];
+            for i in 0..zip.len() {
+                if let Some(zip_path) =
+                    try_extract_entry(&mut zip, i, &map_path, &excluded, config, &mut skipped)?
+                {
+                    extracted.insert(zip_path);
+                }
+            }
+            (extracted, skipped)
+        }
+        jobs => extract_parallel(path, zip.len(), jobs, &map_path, &excluded, config)?,
+    };
+    skipped.sort();
+    if !skipped.is_empty() {
+        warn!(
+            "[{}] {} files failed to extract, this is a partial import",
+            "!".yellow(),
+            skipped.len()
+        );
+    }
+    if config.incremental {
+        remove_stale_files(&map_path, &map_path, &extracted)
+            .map_err(|x| anyhow!("Failed to remove old map files: {}", x))?;
+    }
+    extract_span.exit();
+
+    let _stage_span = info_span!("stage", repo = %relative).entered();
+    if config.keep_layout {
+        manifest::Manifest::from_archive(&mut zip).write(&repo_path)?;
+    }
+    // The metadata of new maps is only known now that they are extracted
+    if needs_scaffold && let Some(template) = readme::template(config) {
+        let difficulties = metadata::read_difficulties(&map_path)?;
+        std::fs::write(
+            repo_path.join("README.md"),
+            readme::render(&template, &name, &difficulties, &config.metadata_from),
+        )
+        .map_err(|x| anyhow!("Failed to write README.md: {}", x))?;
+    } else if !config.raw {
+        readme::update(
+            &repo_path.join("README.md"),
+            &map_path,
+            &config.metadata_from,
+        )?;
+    }
+
+    if config.keep_latest_osz {
+        let difficulties = metadata::read_difficulties(&map_path)?;
+        let difficulty = config
+            .metadata_from
+            .select(&difficulties)
+            .cloned()
+            .unwrap_or_default();
+        let target = repo_path.join(kept_osz_path(&config.keep_osz_path, &name, &difficulty)?);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|x| anyhow!("Failed to create directory for the latest .osz: {}", x))?;
+        }
+        std::fs::copy(path, target)
+            .map_err(|x| anyhow!("Failed to copy the latest .osz: {}", x))?;
+    }
+    match config.smart_lfs {
+        true => {
+            lfs::track(&repo, &repo_path)?;
+            lfs::stage(&repo, &pathspec)?;
+        }
+        false => git_add_all(&repo, &pathspec)?,
+    }
+
+    Ok(StagedImport {
+        name,
+        repo_path,
+        pathspec,
+        skipped,
+        _lock: lock,
+    })
+}
+
+/// Commits a staged import, `osz` is the archive it was imported from.
+/// Returns `false` without committing if nothing changed since the last commit,
+/// unless `allow_empty` is set
+fn commit_import(
+    config: &Config,
+    staged: &StagedImport,
+    osz: &Path,
+    allow_empty: bool,
+) -> anyhow::Result<bool> {
+    let name = &staged.name;
+    let pathspec = &staged.pathspec;
+    let _span = info_span!("commit", repo = %name).entered();
+    let repo = match config.monorepo {
+        true => Repository::open(&config.repos),
+        false => Repository::open(&staged.repo_path),
+    };
+    let repo = repo.map_err(|x| anyhow!("Failed to open repository: {}", x))?;
+    let map_path = map_directory(&staged.repo_path, config);
+
+    match config.smart_lfs {
+        true => lfs::stage(&repo, pathspec)?,
+        false => git_add_all(&repo, pathspec)?,
+    }
+    let tree = repo
+        .index()
+        .and_then(|mut x| x.write_tree())
+        .map_err(|x| anyhow!("Failed to write tree: {}", x))?;
+    let head_tree = repo.head().and_then(|x| x.peel_to_tree()).ok();
+    let unchanged = head_tree.is_some_and(|x| x.id() == tree);
+    if unchanged && !allow_empty {
+        return Ok(false);
+    }
+
+    let map_prefix = match (config.monorepo, config.raw) {
+        (true, _) => Path::new(pathspec).join("map"),
+        (false, true) => PathBuf::new(),
+        (false, false) => PathBuf::from("map"),
+    };
+    let difficulties = git_changed_difficulties(&repo, &map_prefix, &map_path);
+    if config.changelog && !unchanged {
+        prepend_changelog(&staged.repo_path, &difficulties)?;
+        // libgit2 doesn't match `./` pathspecs
+        let changelog = match pathspec.as_str() {
+            "." => CHANGELOG.to_string(),
+            dir => format!("{}/{}", dir, CHANGELOG),
+        };
+        git_add_all(&repo, &changelog)?;
+    }
+    let version = match config.version_tags && !unchanged {
+        true => {
+            let version = bump_version(&staged.repo_path)?;
+            let file = match pathspec.as_str() {
+                "." => VERSION_FILE.to_string(),
+                dir => format!("{}/{}", dir, VERSION_FILE),
+            };
+            git_add_all(&repo, &file)?;
+            Some(version)
+        }
+        false => None,
+    };
+
+    // libgit2 never runs hooks, so installed hooks are run here
+    if config.hooks_dir.is_some() {
+        run_hook(&repo, "pre-commit")?;
+    }
+
+    info!("[{}] Commiting changes...", "i".cyan());
+    let summary = git_change_summary(&repo, &map_prefix)?;
+    let map_difficulties = match metadata::read_difficulties(&map_path) {
+        Ok(difficulties) => difficulties,
+        Err(err) => {
+            if config.collab_trailers {
+                warn!("[{}] Failed to read map metadata: {}", "!".yellow(), err);
+            }
+            vec![]
+        }
+    };
+    let commit_message = import_message(
+        name,
+        config,
+        &map_difficulties,
+        &metadata::map_files(&map_path),
+        &summary,
+        unchanged,
+        &staged.skipped,
+    );
+    // Without a scaffolding commit (`--raw`, `--no-scaffold-commit`) the first import
+    // is the root commit
+    let commit = match repo.head() {
+        Ok(_) => git_commit(&repo, config, &commit_message)?,
+        Err(_) => git_initial_commit(&repo, config, &commit_message)?,
+    };
+    if !difficulties.is_empty() {
+        info!(
+            "[{}] Changed difficulties: {}",
+            "i".cyan(),
+            difficulties.join(", ").cyan()
+        );
+    }
+    if config.osz_notes {
+        git_note_osz(&repo, config, commit, osz)?;
+    }
+    // The commit is already made, a tag that can't be created is only a warning
+    if let Some(version) = version {
+        match git_version_tag(&repo, config, pathspec, version, commit, &commit_message) {
+            Ok(tag) => info!("[{}] Tagged {}", "+".green(), tag.cyan()),
+            Err(err) => warn!("[{}] Failed to tag v{}: {}", "!".yellow(), version, err),
+        }
+    }
+
+    if let Some(branch) = &config.archive_branch {
+        info!("[{}] Archiving .osz to {}...", "i".cyan(), branch.cyan());
+        git_archive_osz(
+            &repo,
+            config,
+            branch,
+            osz,
+            &(name.clone() + ".osz"),
+            &commit_title(config, name, "Map archive"),
+        )?;
+    }
+
+    // Maintenance failing doesn't make the import fail
+    if config.auto_gc
+        && let Err(err) = auto_gc(&repo, config.gc_threshold)
+    {
+        warn!("[{}] git gc failed! {}", "!".yellow(), err);
+    }
+    if let Some(limit) = config.confirm_large_repo {
+        check_repo_size(&repo, limit);
+    }
+
+    // The import itself already succeeded, so release failures are only warnings
+    if config.github_release {
+        info!("[{}] Creating GitHub release...", "i".cyan());
+        match github_release(&repo, config, name, commit, osz, &commit_message) {
+            Ok(url) => info!("[{}] Created draft release {}", "+".green(), url.cyan()),
+            Err(err) => warn!("[{}] GitHub release failed! {}", "!".yellow(), err),
+        }
+    }
+    if let Some(sound) = &config.import_sound {
+        feedback::import_complete(sound);
+    }
+
+    Ok(true)
+}
+
+/// Changelog kept with `--changelog`, relative to the directory containing `map/`
+const CHANGELOG: &str = "CHANGELOG.md";
+
+/// Adds an entry for the current import to the top of the changelog in `repo_path`
+///
+/// Entries can't contain the hash of the commit they are part of,
+/// `git log -- CHANGELOG.md` finds it
+fn prepend_changelog(repo_path: &Path, difficulties: &[String]) -> anyhow::Result<()> {
+    const TITLE: &str = "# Changelog\n\n";
+    let path = repo_path.join(CHANGELOG);
+    let existing = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => anyhow::bail!("Failed to read {}: {}", CHANGELOG, err),
+    };
+    let existing = existing.strip_prefix(TITLE).unwrap_or(&existing);
+
+    // RFC 3339 in UTC, e.g. 2025-02-14T18:30:05Z
+    let now = humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string();
+    let mut entry = format!("## {} {} UTC\n\n", &now[..10], &now[11..16]);
+    match difficulties.is_empty() {
+        true => entry += "- Updated files other than difficulties\n",
+        false => {
+            for difficulty in difficulties {
+                entry += &format!("- {}\n", difficulty);
+            }
+        }
+    }
+    if !existing.is_empty() {
+        entry += "\n";
+    }
+
+    std::fs::write(&path, format!("{}{}{}", TITLE, entry, existing))
+        .map_err(|x| anyhow!("Failed to write {}: {}", CHANGELOG, x))
+}
+
+/// Last version tagged with `--version-tags`, relative to the directory containing `map/`
+const VERSION_FILE: &str = ".gitosu/version";
+
+/// Increments the version recorded in `repo_path` and returns it, starting at 1
+fn bump_version(repo_path: &Path) -> anyhow::Result<u64> {
+    let path = repo_path.join(VERSION_FILE);
+    let last = match std::fs::read_to_string(&path) {
+        Ok(text) => text
+            .trim()
+            .parse::<u64>()
+            .map_err(|x| anyhow!("{} is not a version number: {}", VERSION_FILE, x))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => anyhow::bail!("Failed to read {}: {}", VERSION_FILE, err),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|x| anyhow!("Failed to create {}: {}", parent.to_string_lossy(), x))?;
+    }
+    std::fs::write(&path, format!("{}\n", last + 1))
+        .map_err(|x| anyhow!("Failed to write {}: {}", VERSION_FILE, x))?;
+    Ok(last + 1)
+}
+
+/// Creates the annotated tag of `version` on `commit`, prefixed with the map's directory
+/// in monorepo mode as tags are shared by every map. Returns the tag name
+fn git_version_tag(
+    repo: &Repository,
+    config: &Config,
+    pathspec: &str,
+    version: u64,
+    commit: git2::Oid,
+    message: &str,
+) -> anyhow::Result<String> {
+    let tag = match pathspec {
+        "." => format!("v{}", version),
+        // Characters git doesn't allow in ref names
+        dir => format!(
+            "{}/v{}",
+            dir.replace(|x: char| x.is_control() || " ~^:?*[\\".contains(x), "-"),
+            version
+        ),
+    };
+    if !git2::Reference::is_valid_name(&format!("refs/tags/{}", tag)) {
+        anyhow::bail!("{} is not a valid tag name", tag);
+    }
+    let target = repo.find_object(commit, None)?;
+    let title = message.lines().next().unwrap_or_default();
+    repo.tag(&tag, &target, &git_signature(repo, config), title, false)?;
+    Ok(tag)
+}
+
+/// Stashes changes in the working tree that aren't staged (manual edits), failing instead
+/// with `--no-overwrite-dirty`
+///
+/// Staged changes belong to exports batched with `--batch-count` and are kept as they are
+fn stash_manual_changes(
+    repo: &Repository,
+    repo_path: &Path,
+    pathspec: &str,
+    config: &Config,
+) -> anyhow::Result<()> {
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    // libgit2 doesn't match `.` as a pathspec
+    if pathspec != "." {
+        options.pathspec(pathspec);
+    }
+    let statuses = repo
+        .statuses(Some(&mut options))
+        .map_err(|x| anyhow!("Failed to check for uncommitted changes: {}", x))?;
+    let unstaged = git2::Status::WT_NEW
+        | git2::Status::WT_MODIFIED
+        | git2::Status::WT_DELETED
+        | git2::Status::WT_RENAMED
+        | git2::Status::WT_TYPECHANGE;
+    let changed: Vec<String> = statuses
+        .iter()
+        .filter(|x| x.status().intersects(unstaged))
+        .filter_map(|x| x.path().map(|x| x.to_string()))
+        .collect();
+    if changed.is_empty() {
+        return Ok(());
+    }
+    if config.no_overwrite_dirty {
+        anyhow::bail!(
+            "{} has {} uncommitted changes! Commit or stash them first",
+            repo_path.to_string_lossy(),
+            changed.len()
+        );
+    }
+
+    const MESSAGE: &str = "gitosu: manual changes before import";
+    match config.monorepo {
+        // libgit2 stashes the whole working tree even when given a pathspec,
+        // so git has to stash only this map for the other maps to keep their changes
+        true => {
+            let output = Command::new("git")
+                .arg("-C")
+                .arg(repo.workdir().unwrap_or(repo.path()))
+                .args(["stash", "push", "--include-untracked", "--keep-index"])
+                .args(["--message", MESSAGE, "--", pathspec])
+                .output()
+                .map_err(|x| anyhow!("Failed to run git: {}", x))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "Failed to stash uncommitted changes: git stash exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+        }
+        false => {
+            // Stashing needs a mutable repository
+            let mut repo = Repository::open(repo.path())
+                .map_err(|x| anyhow!("Failed to open repository: {}", x))?;
+            let signature = git_signature(&repo, config);
+            let flags = git2::StashFlags::INCLUDE_UNTRACKED | git2::StashFlags::KEEP_INDEX;
+            repo.stash_save(&signature, MESSAGE, Some(flags))
+                .map_err(|x| anyhow!("Failed to stash uncommitted changes: {}", x))?;
+        }
+    }
+    warn!(
+        "[{}] Stashed {} uncommitted changes in {} before importing, get them back with `git stash pop`",
+        "!".yellow(),
+        changed.len(),
+        repo_path.to_string_lossy().yellow()
+    );
+    Ok(())
+}
+
+/// Runs `git gc` on `repo` if it has at least `threshold` loose objects
+///
+/// libgit2 can't pack repositories, so this needs git to be installed
+fn auto_gc(repo: &Repository, threshold: usize) -> anyhow::Result<()> {
+    let loose = loose_objects(&repo.path().join("objects"))
+        .map_err(|x| anyhow!("Failed to count loose objects: {}", x))?;
+    if loose < threshold {
+        debug!("{} loose objects, not running git gc", loose);
+        return Ok(());
+    }
+    info!(
+        "[{}] Running git gc ({} loose objects)...",
+        "i".cyan(),
+        loose
+    );
+    let output = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["gc", "--quiet"])
+        .output()
+        .map_err(|x| anyhow!("Failed to run git: {}", x))?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "git gc exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Counts the objects stored in their own file in the objects directory
+fn loose_objects(objects: &Path) -> std::io::Result<usize> {
+    let mut count = 0;
+    for entry in std::fs::read_dir(objects)? {
+        let entry = entry?;
+        // Loose objects are in directories named after the first two hex digits of their id,
+        // unlike `pack` and `info`
+        let name = entry.file_name();
+        let is_fanout = name.len() == 2
+            && name
+                .to_string_lossy()
+                .chars()
+                .all(|c| c.is_ascii_hexdigit());
+        if is_fanout && entry.file_type()?.is_dir() {
+            count += std::fs::read_dir(entry.path())?.count();
+        }
+    }
+    Ok(count)
+}
+
+/// Warns if the git directory of `repo` is larger than `limit` megabytes
+fn check_repo_size(repo: &Repository, limit: u64) {
+    let size = match dir_size(repo.path()) {
+        Ok(size) => size,
+        Err(err) => {
+            warn!(
+                "[{}] Failed to check the repository size: {}",
+                "!".yellow(),
+                err
+            );
+            return;
+        }
+    };
+    if size <= limit * 1024 * 1024 {
+        return;
+    }
+    warn!(
+        "[{}] {} {} is {}, larger than the {} MB limit! Track .osz files with Git LFS, \
+         use --archive-branch, or stop using --keep-latest-osz",
+        "!".yellow(),
+        "Repository".yellow().bold(),
+        repo.workdir()
+            .unwrap_or(repo.path())
+            .to_string_lossy()
+            .yellow(),
+        duplicates::format_size(size).yellow().bold(),
+        limit
+    );
+}
+
+/// Total size of all files in `path` and its subdirectories
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else if file_type.is_file() {
+            size += entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Creates a draft GitHub release for `commit` with the archive at `osz` attached
+fn github_release(
+    repo: &Repository,
+    config: &Config,
+    name: &str,
+    commit: git2::Oid,
+    osz: &Path,
+    message: &str,
+) -> anyhow::Result<String> {
+    let coordinates = match &config.github_repo {
+        Some(template) => template.replace("{name}", name),
+        None => github::origin_repo(repo).ok_or(anyhow!(
+            "Unknown GitHub repository, use --github-repo or an origin remote on github.com"
+        ))?,
+    };
+    let branch = repo
+        .head()
+        .ok()
+        .and_then(|x| x.shorthand().map(|x| x.to_string()))
+        .ok_or(anyhow!("Failed to determine the current branch"))?;
+    let short = &commit.to_string()[..7];
+    let body = message
+        .split_once("\n\n")
+        .map(|(_, body)| body.to_string())
+        .unwrap_or_default();
+    github::create_release(&github::Release {
+        repo: &coordinates,
+        token: config.github_token.as_deref().unwrap_or_default(),
+        tag: format!("gitosu-{}", short),
+        name: format!("{} ({})", name, short),
+        body,
+        branch,
+        osz,
+        asset_name: format!("{}.osz", name),
+    })
+}
+
+impl Commands {
+    pub fn run(self, config: Arc<Config>) -> anyhow::Result<()> {
+        match self {
+            Self::Import {
+                mut files,
+                use_repository,
+                from_list,
+                open,
+                open_with,
+                allow_empty,
+            } => {
+                if let Some(list) = from_list {
+                    let files = read_import_list(&list)?;
+                    let _lock = InstanceLock::acquire(&config)?;
+                    return import_many(&files, config, open, open_with.as_deref(), allow_empty);
+                }
+                if files.len() > 1 {
+                    if use_repository.is_some() {
+                        anyhow::bail!("--use-repository can only be used with a single file");
+                    }
+                    if files.iter().any(|x| x.as_os_str() == "-") {
+                        anyhow::bail!("stdin (`-`) can't be imported together with other files");
+                    }
+                    let files: Vec<_> = files.into_iter().map(|x| (x, None)).collect();
+                    let _lock = InstanceLock::acquire(&config)?;
+                    return import_many(&files, config, open, open_with.as_deref(), allow_empty);
+                }
+
+                let _lock = InstanceLock::acquire(&config)?;
+                let file = files.remove(0);
+                // Archives from stdin are spooled to disk first, so they are
+                // read with random access just like regular files
+                let _stdin_file;
+                let file = if file.as_os_str() == "-" {
+                    if use_repository.is_none() {
+                        anyhow::bail!("--use-repository is required when importing from stdin");
+                    }
+                    _stdin_file = TempFile::from_reader(&mut std::io::stdin().lock(), &config)?;
+                    _stdin_file.path.clone()
+                } else {
+                    file
+                };
+
+                match std::fs::exists(&file) {
+                    Ok(true) => {}
+                    Ok(false) => anyhow::bail!("File not found!"),
+                    Err(err) => anyhow::bail!("Failed to check if file exists: {}", err),
+                };
+                // The error is logged already, like with several files
+                let Some(imported) =
+                    import_and_report(&file, config.clone(), use_repository, allow_empty)
+                else {
+                    anyhow::bail!("Import of {} failed", file.to_string_lossy());
+                };
+                // On stdout for scripts, the log hides it by default
+                if !imported.changed {
+                    println!("Map is already up to date, nothing to commit");
+                }
+                if (open || open_with.is_some())
+                    && let Err(err) = open_directory(&imported.repo_path, open_with.as_deref())
+                {
+                    warn!("[{}] Failed to open repository: {}", "!".yellow(), err);
+                }
+            }
+            Self::Export {
+                all: true,
+                output,
+                compression,
+                compression_level,
+                ..
+            } => {
+                let output = output.unwrap_or_else(|| PathBuf::from("."));
+                export::export_all(&config, &output, compression, compression_level)?;
+            }
+            Self::Export {
+                repository,
+                all: false,
+                output,
+                compression,
+                compression_level,
+                at,
+            } => {
+                let managed = repos::ManagedRepo::from_path(&repository_dir(
+                    &config,
+                    repository.as_deref(),
+                )?)?;
+                let output =
+                    output.unwrap_or_else(|| PathBuf::from(format!("{}.osz", managed.name)));
+                match at {
+                    Some(rev) => {
+                        let repo = git2::Repository::discover(&managed.path)
+                            .map_err(|x| anyhow!("Failed to open repository: {}", x))?;
+                        let workdir = repo
+                            .workdir()
+                            .ok_or(anyhow!("Repository has no working directory"))?
+                            .canonicalize()?;
+                        // The map directory is in a subdirectory in monorepos,
+                        // and the repository root in raw repositories
+                        let map_prefix = match managed.raw {
+                            true => PathBuf::new(),
+                            false => managed.path.strip_prefix(&workdir)?.join("map"),
+                        };
+                        export::export_revision(
+                            &repo,
+                            &rev,
+                            &map_prefix,
+                            &output,
+                            compression,
+                            compression_level,
+                        )?;
+                    }
+                    None => export::export_map(
+                        &managed.map_path(),
+                        &output,
+                        compression,
+                        compression_level,
+                    )?,
+                }
+                info!("Export completed!");
+            }
+            // Handled before the config is parsed
+            Self::Doctor { .. } | Self::Config => unreachable!(),
+            Self::List { output_format } => repos::list(&config, output_format)?,
+            Self::Fsck { output_format } => fsck::run(&config, output_format)?,
+            Self::Audit { output_format } => audit::run(&config, output_format)?,
+            Self::Refresh {
+                repository,
+                all,
+                full,
+            } => {
+                let _lock = InstanceLock::acquire(&config)?;
+                let managed = match all {
+                    true => repos::scan(&config)?,
+                    false => {
+                        let path = repository_dir(&config, repository.as_deref())?;
+                        vec![repos::ManagedRepo::from_path(&path)?]
+                    }
+                };
+                let (mut refreshed, mut failed) = (0, 0);
+                for managed in &managed {
+                    match readme::refresh(&config, managed, full) {
+                        Ok(true) => refreshed += 1,
+                        Ok(false) => {}
+                        Err(err) => {
+                            failed += 1;
+                            error!(
+                                "[{}] Failed to refresh {}: {}",
+                                "x".red(),
+                                managed.name,
+                                err
+                            );
+                        }
+                    }
+                }
+                info!("Refreshed {} of {} repositories", refreshed, managed.len());
+                if failed > 0 {
+                    anyhow::bail!("{} repositories failed to refresh", failed);
+                }
+            }
+            Self::Diff {
+                repository,
+                from,
+                to,
+            } => {
+                let path = repository_dir(&config, Some(&repository))?;
+                let managed = repos::ManagedRepo::from_path(&path)?;
+                diff::run(&config, &managed, &from, &to)?;
+            }
+            Self::Duplicates { repository, all } => {
+                let managed = match all {
+                    true => repos::scan(&config)?,
+                    false => {
+                        let path = repository_dir(&config, repository.as_deref())?;
+                        vec![repos::ManagedRepo::from_path(&path)?]
+                    }
+                };
+                duplicates::run(&config, &managed)?;
+            }
+            Self::Squash {
+                repository,
+                all,
+                keep,
+                older_than,
+            } => {
+                let older_than = older_than
+                    .map(|x| {
+                        // Also has to be something to subtract from the current time
+                        x.checked_mul(24 * 60 * 60)
+                            .map(Duration::from_secs)
+                            .filter(|x| SystemTime::now().checked_sub(*x).is_some())
+                            .ok_or(anyhow!("--older-than is too large"))
+                    })
+                    .transpose()?;
+                let _lock = InstanceLock::acquire(&config)?;
+                let managed = match all {
+                    true => repos::scan(&config)?,
+                    false => {
+                        let path = repository_dir(&config, repository.as_deref())?;
+                        vec![repos::ManagedRepo::from_path(&path)?]
+                    }
+                };
+                if !config.force && !confirm("Squashing rewrites history, continue?") {
+                    anyhow::bail!("Not squashing (use --force to skip this question)");
+                }
+                let (mut removed, mut failed) = (0, 0);
+                for managed in &managed {
+                    match squash::run(&config, managed, keep, older_than) {
+                        Ok(n) => removed += n,
+                        Err(err) => {
+                            failed += 1;
+                            error!("[{}] Failed to squash {}: {}", "x".red(), managed.name, err);
+                        }
+                    }
+                }
+                info!("Removed {} commits", removed);
+                if failed > 0 {
+                    anyhow::bail!("{} repositories couldn't be squashed", failed);
+                }
+            }
+            Self::ResetFrom {
+                file,
+                use_repository,
+                backup,
+            } => {
+                let _lock = InstanceLock::acquire(&config)?;
+                reset::run(&config, &file, use_repository, backup)?;
+            }
+            Self::Message {
+                file,
+                use_repository,
+            } => message::run(&config, &file, use_repository)?,
+            Self::Name {
+                file,
+                use_repository,
+                output_format,
+            } => {
+                if file.file_name().is_none() {
+                    anyhow::bail!("Not a file path!");
+                }
+                // Names can come from the archive's metadata
+                let mut zip = File::open(&file).ok().and_then(|x| ZipArchive::new(x).ok());
+                // Same path an import would use, nested by mode or found by --discover
+                let (name, relative) = match zip.as_mut() {
+                    Some(zip) => resolve_repo(&file, use_repository, zip, &config)?,
+                    None => {
+                        let name = resolve_repo_name(&file, use_repository, None, &config);
+                        (name.clone(), name)
+                    }
+                };
+                let path = config.repos.join(&relative);
+                let exists = match (config.monorepo, config.raw) {
+                    (true, _) => path.is_dir(),
+                    (false, true) => path.join(".git").exists(),
+                    (false, false) => path.join("map").is_dir(),
+                };
+                match output_format {
+                    OutputFormat::Table => {
+                        println!("{}", name);
+                        println!("exists: {}", exists);
+                    }
+                    format => repos::print_rows(
+                        format,
+                        &["name", "path", "exists"],
+                        &[vec![name.into(), relative.into(), exists.into()]],
+                    ),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Archive entries (as asset keys) left out with `--difficulties`, `--no-storyboard`
+/// and `--no-video`, including assets only the left out difficulties or storyboards use
+fn excluded_entries(
+    zip: &mut ZipArchive<File>,
+    config: &Config,
+) -> anyhow::Result<HashSet<String>> {
+    let password = config.password.as_deref();
+    let difficulties = metadata::read_archive_difficulties(zip, password)?;
+    let (kept, skipped): (Vec<_>, Vec<_>) =
+        difficulties
+            .iter()
+            .partition(|d| match &config.difficulties {
+                Some(selected) => selected.iter().any(|x| x.eq_ignore_ascii_case(&d.version)),
+                None => true,
+            });
+    if kept.is_empty() {
+        anyhow::bail!("None of the difficulties match --difficulties!");
+    }
+
+    let storyboard = metadata::read_archive_storyboard_assets(zip, password)?;
+    let mut referenced = HashSet::new();
+    if !config.no_storyboard {
+        referenced.extend(storyboard.iter().cloned());
+    }
+    for difficulty in &kept {
+        referenced.extend(difficulty.assets.iter().cloned());
+    }
+
+    let mut excluded = HashSet::new();
+    let entries: Vec<String> = zip.file_names().map(metadata::asset_key).collect();
+    if config.no_storyboard {
+        excluded.extend(entries.iter().filter(|x| x.ends_with(".osb")).cloned());
+        excluded.extend(storyboard.into_iter().filter(|x| !referenced.contains(x)));
+    }
+    // Videos are left out even if a difficulty uses them, osu! plays the map without
+    if config.no_video {
+        excluded.extend(entries.iter().filter(|x| metadata::is_video(x)).cloned());
+    }
+    // Skipped difficulties are left out of `referenced`, so the assets only they use
+    // are orphaned as well
+    if config.prune_orphan_assets {
+        for entry in entries {
+            if metadata::is_prunable(&entry)
+                && !referenced.contains(&entry)
+                && !excluded.contains(&entry)
+            {
+                info!(
+                    "[{}] Leaving out {}, nothing references it",
+                    "i".cyan(),
+                    entry.cyan()
+                );
+                excluded.insert(entry);
+            }
+        }
+    }
+    for difficulty in &skipped {
+        info!(
+            "[{}] Skipping difficulty {}",
+            "i".cyan(),
+            difficulty.version.cyan()
+        );
+        excluded.insert(metadata::asset_key(&difficulty.file.to_string_lossy()));
+        excluded.extend(
+            difficulty
+                .assets
+                .iter()
+                .filter(|x| !referenced.contains(*x))
+                .cloned(),
+        );
+    }
+    Ok(excluded)
+}
+
+/// Extracts entry `index` of the archive into `map_path`,
+/// returning its path in the map directory or `None` if it was skipped
+fn extract_entry(
+    zip: &mut ZipArchive<File>,
+    index: usize,
+    map_path: &Path,
+    excluded: &HashSet<String>,
+    config: &Config,
+) -> anyhow::Result<Option<PathBuf>> {
+    let mut zip_file = archive::open_entry(zip, index, config.password.as_deref())?;
+    let zip_path = match archive::entry_path(zip_file.name()) {
+        Some(p) => p,
         None => {
-            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
-            file_name[..(file_name.len() - 4)].to_string()
+            warn!("[{}] Map archive contains forbidden files!", "!".yellow());
+            return Ok(None);
+        }
+    };
+    // Extracting into the repository root with --raw could overwrite git's own files
+    if zip_path.components().next() == Some(Component::Normal(".git".as_ref())) {
+        warn!("[{}] Map archive contains forbidden files!", "!".yellow());
+        return Ok(None);
+    }
+    // Parent directories are created for the files in them
+    if zip_file.is_dir() {
+        return Ok(None);
+    }
+    if excluded.contains(&metadata::asset_key(&zip_path.to_string_lossy())) {
+        debug!("skipping {}", zip_path.to_string_lossy());
+        return Ok(None);
+    }
+    let zip_path = match config.normalize_names {
+        true => normalize::path(&zip_path),
+        false => zip_path,
+    };
+    let target_path = map_path.join(&zip_path);
+    // Rewritten text files are compared after rewriting
+    let rewrite_eol = config.normalize_eol.applies_to(&zip_path);
+    let rewrite_names = config.normalize_names && normalize::has_references(&zip_path);
+    let normalized = match rewrite_eol || rewrite_names {
+        true => {
+            let mut bytes = vec![];
+            zip_file
+                .read_to_end(&mut bytes)
+                .map_err(|x| anyhow!("Failed to read file: {}", x))?;
+            if rewrite_names && let Some(rewritten) = normalize::references(&bytes) {
+                bytes = rewritten;
+            }
+            match rewrite_eol {
+                true => Some(config.normalize_eol.apply(&bytes)),
+                false => Some(bytes),
+            }
+        }
+        false => None,
+    };
+    let unchanged = config.incremental
+        && match &normalized {
+            Some(bytes) => std::fs::read(&target_path).is_ok_and(|x| x == *bytes),
+            None => file_matches(&target_path, zip_file.size(), zip_file.crc32()),
+        };
+    if unchanged {
+        debug!("{} is unchanged", zip_path.to_string_lossy());
+        return Ok(Some(zip_path));
+    }
+    debug!(
+        "copying {} into {}",
+        zip_path.to_string_lossy(),
+        target_path.to_string_lossy()
+    );
+    let parent = target_path.parent().ok_or(anyhow!("Incorrect file path"))?;
+    // Safe to race with other extraction threads, existing directories aren't an error
+    create_dirs(parent, config.dir_mode)
+        .map_err(|x| anyhow!("Failed to make parent directories for file: {}", x))?;
+    // Writing into a hard link would change the stored file of every map linked to it
+    if config.shared_store.is_some() {
+        let _ = std::fs::remove_file(&target_path);
+    }
+    let mut file = File::create(&target_path)
+        .map_err(|x| anyhow!("Failed to open target file for writing: {}", x))?;
+    let written = match normalized {
+        Some(bytes) => file.write_all(&bytes).map(|_| bytes.len() as u64),
+        None => std::io::copy(&mut zip_file, &mut file),
+    };
+    let written = match written {
+        Ok(written) => written,
+        Err(err) => {
+            // Don't leave a truncated file behind
+            drop(file);
+            let _ = std::fs::remove_file(&target_path);
+            anyhow::bail!("Failed to write file: {}", err);
+        }
+    };
+    metrics::add(&metrics::BYTES_PROCESSED, written);
+    drop(file);
+    set_mode(&target_path, config.file_mode)
+        .map_err(|x| anyhow!("Failed to set file permissions: {}", x))?;
+    if let Some(store) = &config.shared_store {
+        // The extracted copy is kept if linking fails, e.g. on another filesystem
+        if let Err(err) = store::link(store, &target_path) {
+            warn!(
+                "[{}] Failed to deduplicate {}: {}",
+                "!".yellow(),
+                zip_path.to_string_lossy(),
+                err
+            );
+        }
+    }
+    Ok(Some(zip_path))
+}
+
+/// Extracts an entry like [`extract_entry`], but with `--best-effort` a failed entry
+/// is logged and added to `skipped` instead of failing the import
+fn try_extract_entry(
+    zip: &mut ZipArchive<File>,
+    index: usize,
+    map_path: &Path,
+    excluded: &HashSet<String>,
+    config: &Config,
+    skipped: &mut Vec<String>,
+) -> anyhow::Result<Option<PathBuf>> {
+    match extract_entry(zip, index, map_path, excluded, config) {
+        Err(err) if config.best_effort => {
+            let name = zip
+                .by_index_raw(index)
+                .map(|x| x.name().to_string())
+                .unwrap_or_else(|_| format!("entry #{}", index));
+            warn!("[{}] Skipping {}: {}", "!".yellow(), name, err);
+            skipped.push(name);
+            Ok(None)
+        }
+        result => result,
+    }
+}
+
+/// Extracts all `len` entries of the archive at `path` using `jobs` threads,
+/// each with its own handle to the archive, returning the extracted and skipped entries
+fn extract_parallel(
+    path: &Path,
+    len: usize,
+    jobs: usize,
+    map_path: &Path,
+    excluded: &HashSet<String>,
+    config: &Config,
+) -> anyhow::Result<(HashSet<PathBuf>, Vec<String>)> {
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs.min(len))
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<(Vec<PathBuf>, Vec<String>)> {
+                    let file =
+                        File::open(path).map_err(|x| anyhow!("Failed to open .osz: {}", x))?;
+                    let mut zip = ZipArchive::new(file)
+                        .map_err(|x| anyhow!("Failed to open .osz as a zip archive: {}", x))?;
+                    let mut extracted = vec![];
+                    let mut skipped = vec![];
+                    while !failed.load(Ordering::Relaxed) {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= len {
+                            break;
+                        }
+                        match try_extract_entry(
+                            &mut zip,
+                            i,
+                            map_path,
+                            excluded,
+                            config,
+                            &mut skipped,
+                        ) {
+                            Ok(Some(zip_path)) => extracted.push(zip_path),
+                            Ok(None) => {}
+                            Err(err) => {
+                                // Stop the other threads early
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+                    Ok((extracted, skipped))
+                })
+            })
+            .collect();
+
+        let mut extracted = HashSet::new();
+        let mut skipped = vec![];
+        for worker in workers {
+            let (paths, names) = worker
+                .join()
+                .map_err(|_| anyhow!("Extraction thread panicked"))??;
+            extracted.extend(paths);
+            skipped.extend(names);
+        }
+        Ok((extracted, skipped))
+    })
+}
+
+/// Checks if the file at `path` has the given size and CRC32
+fn file_matches(path: &Path, size: u64, crc32: u32) -> bool {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_file() && meta.len() == size => {}
+        _ => return false,
+    }
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(_) => return false,
+        }
+    }
+    hasher.finalize() == crc32
+}
+
+/// Removes files in `dir` that weren't extracted from the archive and empty directories
+fn remove_stale_files(
+    map_path: &Path,
+    dir: &Path,
+    extracted: &HashSet<PathBuf>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path == map_path.join(".git") {
+            continue;
+        }
+        if path.is_dir() {
+            remove_stale_files(map_path, &path, extracted)?;
+            if std::fs::read_dir(&path)?.next().is_none() {
+                std::fs::remove_dir(&path)?;
+            }
+        } else {
+            let relative = path.strip_prefix(map_path).unwrap_or(&path);
+            if !extracted.contains(relative) {
+                debug!("removing {}", relative.to_string_lossy());
+                std::fs::remove_file(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Deletes everything in the repository at `repo_path` except its `.git` directory
+fn clear_repository(repo_path: &Path) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(repo_path)? {
+        let entry = entry?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+        debug!("removing {}", entry.path().to_string_lossy());
+        match entry.file_type()?.is_dir() {
+            true => std::fs::remove_dir_all(entry.path())?,
+            false => std::fs::remove_file(entry.path())?,
+        }
+    }
+    Ok(())
+}
+
+/// Directory the map is extracted into, the repository root itself with `--raw`
+fn map_directory(repo_path: &Path, config: &Config) -> PathBuf {
+    match config.raw {
+        true => repo_path.to_path_buf(),
+        false => repo_path.join("map"),
+    }
+}
+
+/// Recursively copies `template` into `target`, replacing `{map_name}` in text files
+fn copy_template(template: &Path, target: &Path, name: &str) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(template)? {
+        let entry = entry?;
+        let source = entry.path();
+        let destination = target.join(entry.file_name());
+        if entry.file_name() == ".git" {
+            continue;
+        }
+
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&destination)?;
+            copy_template(&source, &destination, name)?;
+        } else {
+            debug!(
+                "copying template file {} into {}",
+                source.to_string_lossy(),
+                destination.to_string_lossy()
+            );
+            let contents = std::fs::read(&source)?;
+            match String::from_utf8(contents) {
+                Ok(text) => std::fs::write(&destination, text.replace("{map_name}", name))?,
+                // Binary files are copied as is
+                Err(err) => std::fs::write(&destination, err.into_bytes())?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Creates the map directory and README (or copies the template) and commits them,
+/// only staging them with `--no-scaffold-commit`
+fn scaffold_repo(
+    repo: &Repository,
+    repo_path: &Path,
+    name: &str,
+    pathspec: &str,
+    config: &Config,
+) -> anyhow::Result<()> {
+    create_dirs(&repo_path.join("map"), config.dir_mode)
+        .map_err(|x| anyhow!("Failed to create map directory: {}", x))?;
+    match &config.template_repo {
+        Some(template) => copy_template(template, repo_path, name)
+            .map_err(|x| anyhow!("Failed to copy template repository: {}", x))?,
+        None => std::fs::write(
+            repo_path.join("README.md"),
+            readme::render(readme::DEFAULT, name, &[], &config.metadata_from),
+        )
+        .map_err(|x| anyhow!("Failed to write README.md: {}", x))?,
+    }
+    if let Some(hooks) = &config.hooks_dir {
+        install_hooks(hooks, repo).map_err(|x| anyhow!("Failed to install git hooks: {}", x))?;
+    }
+    let message = match config.monorepo {
+        true => format!("{}: New osu! map", name),
+        false => "New osu! map".to_string(),
+    };
+    git_add_all(repo, pathspec)?;
+    if config.no_scaffold_commit {
+        return Ok(());
+    }
+    if repo.head().is_ok() {
+        git_commit(repo, config, &message)?;
+    } else {
+        git_initial_commit(repo, config, &message)?;
+    }
+    Ok(())
+}
+
+/// Copies every file in `hooks` into the hooks directory of `repo`, making them executable
+fn install_hooks(hooks: &Path, repo: &Repository) -> anyhow::Result<()> {
+    let target = repo.path().join("hooks");
+    std::fs::create_dir_all(&target)?;
+    for entry in std::fs::read_dir(hooks)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let destination = target.join(entry.file_name());
+        debug!("installing hook {}", destination.to_string_lossy());
+        std::fs::copy(entry.path(), &destination)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&destination, std::fs::Permissions::from_mode(0o755))?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the hook called `name` of `repo` if it exists, failing if it does
+fn run_hook(repo: &Repository, name: &str) -> anyhow::Result<()> {
+    let hook = repo.path().join("hooks").join(name);
+    if !hook.is_file() {
+        return Ok(());
+    }
+    let workdir = repo
+        .workdir()
+        .ok_or(anyhow!("Repository has no working directory"))?;
+    info!("[{}] Running {} hook...", "i".cyan(), name.cyan());
+    // Hooks are usually shell scripts, which Windows can't run on its own
+    let mut command = match cfg!(target_os = "windows") {
+        true => {
+            let mut command = Command::new("sh");
+            command.arg(&hook);
+            command
+        }
+        false => Command::new(&hook),
+    };
+    let status = command
+        .current_dir(workdir)
+        .status()
+        .map_err(|x| anyhow!("Failed to run {} hook: {}", name, x))?;
+    if !status.success() {
+        anyhow::bail!("{} hook failed ({}), nothing was committed", name, status);
+    }
+    Ok(())
+}
+
+/// Temporary .osz file on disk, removed when dropped
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Copies everything from `reader` into a new temporary file in `--temp-dir`
+    fn from_reader(reader: &mut impl std::io::Read, config: &Config) -> anyhow::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        std::fs::create_dir_all(&config.temp_dir)
+            .map_err(|x| anyhow!("Failed to create temporary directory: {}", x))?;
+        // Unique across instances and within one, several instances may share the directory
+        let path = config.temp_dir.join(format!(
+            "gitosu-{}-{}.osz",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let temp = Self { path };
+        let mut file = File::create(&temp.path)
+            .map_err(|x| anyhow!("Failed to create temporary file: {}", x))?;
+        std::io::copy(reader, &mut file)
+            .map_err(|x| anyhow!("Failed to write temporary file: {}", x))?;
+        Ok(temp)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            warn!(
+                "[{}] Failed to remove temporary file {}: {}",
+                "!".yellow(),
+                self.path.to_string_lossy(),
+                err
+            );
+        }
+    }
+}
+
+/// Lock on the repositories directory held while gitosu may write to it,
+/// so two instances can't race on the same repository
+struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Locks `.gitosu/instance.lock` in the repositories directory,
+    /// the lock is released by the OS when gitosu exits, even if it's killed
+    fn acquire(config: &Config) -> anyhow::Result<Option<Self>> {
+        let dir = config.repos.join(".gitosu");
+        std::fs::create_dir_all(&dir)
+            .map_err(|x| anyhow!("Failed to create {}: {}", dir.to_string_lossy(), x))?;
+        // The repositories directory is a git repository in monorepo mode
+        let gitignore = dir.join(".gitignore");
+        if !gitignore.exists() {
+            std::fs::write(&gitignore, "*\n")
+                .map_err(|x| anyhow!("Failed to write {}: {}", gitignore.to_string_lossy(), x))?;
+        }
+        let path = dir.join("instance.lock");
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|x| anyhow!("Failed to open lock file: {}", x))?;
+
+        match file.try_lock() {
+            Ok(_) => {}
+            Err(std::fs::TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                if !config.ignore_lock {
+                    anyhow::bail!(
+                        "Another gitosu instance (PID {}) is using {}! Stop it first or use --ignore-lock",
+                        pid.trim(),
+                        config.repos.to_string_lossy()
+                    );
+                }
+                warn!(
+                    "[{}] Another gitosu instance (PID {}) is using the repositories directory",
+                    "!".yellow(),
+                    pid.trim()
+                );
+                return Ok(None);
+            }
+            Err(std::fs::TryLockError::Error(err)) => {
+                anyhow::bail!("Failed to lock {}: {}", path.to_string_lossy(), err)
+            }
+        }
+
+        file.set_len(0)
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .map_err(|x| anyhow!("Failed to write lock file: {}", x))?;
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+/// Resolves a repository by name, or uses the current directory
+fn repository_dir(config: &Config, name: Option<&str>) -> anyhow::Result<PathBuf> {
+    let path = match name {
+        Some(name) => config.repos.join(name),
+        None => std::env::current_dir()
+            .map_err(|x| anyhow!("Failed to get the current directory: {}", x))?,
+    };
+    if !path.join("map").is_dir() && !repos::is_raw(&path) {
+        anyhow::bail!(
+            "{} is not a gitosu repository (no map directory)",
+            path.to_string_lossy()
+        );
+    }
+    Ok(path)
+}
+
+/// Refuses to create another repository once there are `max`, unless confirmed or forced
+fn check_repo_limit(config: &Config, max: usize) -> anyhow::Result<()> {
+    let count = repos::scan(config)?.len();
+    if count < max {
+        return Ok(());
+    }
+    warn!(
+        "[{}] There are already {} repositories in {} (--max-repos {})",
+        "!".yellow(),
+        count,
+        config.repos.to_string_lossy().yellow(),
+        max
+    );
+    if !config.force && !confirm("Create another one?") {
+        anyhow::bail!(
+            "Refusing to create more than {} repositories (use --force to override)",
+            max
+        );
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, always answering no when not interactive
+fn confirm(question: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Opens a directory using the given command or the system file manager
+fn open_directory(path: &Path, command: Option<&str>) -> anyhow::Result<()> {
+    let mut cmd = match command {
+        Some(command) => {
+            let mut parts = command.split_whitespace();
+            let program = parts.next().ok_or(anyhow!("Open command is empty"))?;
+            let mut cmd = Command::new(program);
+            cmd.args(parts);
+            cmd
         }
+        None if cfg!(target_os = "windows") => Command::new("explorer"),
+        None if cfg!(target_os = "macos") => Command::new("open"),
+        None => Command::new("xdg-open"),
     };
+    cmd.arg(path)
+        .spawn()
+        .map_err(|x| anyhow!("Failed to run open command: {}", x))?;
+    Ok(())
+}
 
-    info!("[{}] Using map repository {}", "i".cyan(), name.cyan());
-
-    let repo_path = config.repos.join(&name);
-    let repo_exists = match std::fs::exists(&repo_path) {
+/// Opens the repository at `path`, initializing it if the directory doesn't exist yet
+///
+/// Returns the repository and whether it was just created
+fn open_or_init_repo(path: &Path, config: &Config) -> anyhow::Result<(Repository, bool)> {
+    let repo_exists = match std::fs::exists(path) {
         Ok(v) => v,
         Err(err) => anyhow::bail!("Failed to check if repository exists: {}", err),
     };
 
-    let repo = if repo_exists {
-        match Repository::open(&repo_path) {
-            Ok(repo) => repo,
+    if repo_exists {
+        match Repository::open(path) {
+            Ok(repo) => Ok((repo, false)),
             Err(err) => anyhow::bail!("Failed to open repository: {}", err),
         }
     } else {
         info!(
             "[{}] Initializing map repository at {}",
             "i".cyan(),
-            repo_path.to_string_lossy().cyan()
+            path.to_string_lossy().cyan()
         );
-        match Repository::init(&repo_path) {
-            Ok(repo) => repo,
+        create_dirs(path, config.dir_mode)
+            .map_err(|x| anyhow!("Failed to create repository directory: {}", x))?;
+        match init_repo(path, config) {
+            Ok(repo) => Ok((repo, true)),
             Err(err) => anyhow::bail!("Failed to init repository: {}", err),
         }
+    }
+}
+
+/// Initializes a repository, shared with the group if `--dir-mode` is group-writable
+fn init_repo(path: &Path, config: &Config) -> Result<Repository, git2::Error> {
+    let mut options = git2::RepositoryInitOptions::new();
+    if config.dir_mode.is_some_and(|x| x & 0o020 != 0) {
+        options.mode(git2::RepositoryInitMode::SHARED_GROUP);
+    }
+    if let Some(branch) = &config.main_branch {
+        options.initial_head(branch);
+    }
+    Repository::init_opts(path, &options)
+}
+
+/// Creates `dir` and its missing parents, giving the created directories `mode`
+fn create_dirs(dir: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    let missing: Vec<&Path> = match mode {
+        Some(_) => dir.ancestors().take_while(|x| !x.exists()).collect(),
+        None => vec![],
     };
-    if !repo_exists {
-        // Initialize basic repository
-        std::fs::write(
-            repo_path.join("README.md"),
-            include_str!("defaultreadme.md").replace("{map_name}", &name),
-        )
-        .map_err(|x| anyhow!("Failed to write README.md: {}", x))?;
-        std::fs::create_dir(repo_path.join("map"))
-            .map_err(|x| anyhow!("Failed to create map directory: {}", x))?;
-        git_add_all(&repo);
-        git_initial_commit(&repo);
+    std::fs::create_dir_all(dir)?;
+    for dir in missing {
+        set_mode(dir, mode)?;
     }
+    Ok(())
+}
 
-    let file = File::open(path).map_err(|x| anyhow!("Failed to open .osz: {}", x))?;
-    let mut zip = ZipArchive::new(file)
-        .map_err(|x| anyhow!("Failed to open .osz as a zip archive: {}", x))?;
-    if zip.len() == 0 {
-        anyhow::bail!("Exported archive is empty!!!");
+/// Sets the permissions of `path` to `mode`, if given
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)),
+        None => Ok(()),
     }
+}
 
-    let map_path = repo_path.join("map");
-    // Removing everything in the map directory
-    // (the reason why you shouldn't touch it)
-    if let Ok(true) = std::fs::exists(&map_path) {
-        std::fs::remove_dir_all(&map_path)
-            .map_err(|x| anyhow!("Failed to clear the map directory: {}", x))?;
+/// Permissions are left to the platform outside of unix
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: Option<u32>) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Parses an octal permission mode like `664`, `0664` or `0o2775`
+fn parse_mode(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "{} is not an octal permission mode like 664",
+            value
+        )),
     }
+}
 
-    // Copy latest files into the map directory
-    info!("[{}] Importing files...", "i".cyan());
-    for i in 0..zip.len() {
-        let mut zip_file = zip.by_index(i)?;
-        let zip_path = match zip_file.enclosed_name() {
-            Some(p) => p,
-            None => {
-                warn!("[{}] Map archive contains forbidden files!", "!".yellow());
-                continue;
-            }
-        };
-        let target_path = map_path.join(&zip_path);
-        debug!(
-            "copying {} into {}",
-            zip_path.to_string_lossy(),
-            target_path.to_string_lossy()
+/// Directory of the archive with `--group-by-mode`, named after its game mode
+fn mode_directory(zip: &mut ZipArchive<File>, config: &Config) -> anyhow::Result<&'static str> {
+    let password = config.password.as_deref();
+    let modes: Vec<&'static str> = metadata::read_archive_difficulties(zip, password)?
+        .iter()
+        .map(|x| x.mode_name())
+        .collect();
+    let first = *modes.first().ok_or(anyhow!(
+        "Archive has no difficulties to determine the game mode"
+    ))?;
+    if modes.iter().all(|x| *x == first) {
+        return Ok(first);
+    }
+    Ok(match config.mixed_modes {
+        MixedModes::First => first,
+        // Ties go to the mode that comes first
+        MixedModes::Most => modes
+            .iter()
+            .copied()
+            .rev()
+            .max_by_key(|mode| modes.iter().filter(|x| *x == mode).count())
+            .unwrap_or(first),
+        MixedModes::Mixed => "mixed",
+    })
+}
+
+/// Opens the monorepo at `path`, initializing it if the directory isn't a repository yet
+fn open_or_init_monorepo(path: &Path, config: &Config) -> anyhow::Result<Repository> {
+    match Repository::open(path) {
+        Ok(repo) => Ok(repo),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            info!(
+                "[{}] Initializing monorepo at {}",
+                "i".cyan(),
+                path.to_string_lossy().cyan()
+            );
+            init_repo(path, config).map_err(|x| anyhow!("Failed to init repository: {}", x))
+        }
+        Err(err) => anyhow::bail!("Failed to open repository: {}", err),
+    }
+}
+
+/// Fills the kept .osz path template, making sure the result stays inside the repository
+fn kept_osz_path(
+    template: &str,
+    name: &str,
+    difficulty: &metadata::Difficulty,
+) -> anyhow::Result<PathBuf> {
+    // Values must not add directories or characters Windows can't handle
+    let clean = |x: &str| x.replace(|c: char| "/\\:*?\"<>|".contains(c), "_");
+    let difficulty = metadata::Difficulty {
+        title: clean(&difficulty.title),
+        artist: clean(&difficulty.artist),
+        creator: clean(&difficulty.creator),
+        version: clean(&difficulty.version),
+        ..difficulty.clone()
+    };
+    // RFC 3339 in UTC, e.g. 2025-02-14T18:30:05Z
+    let now = humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string();
+    let formatted = difficulty
+        .format(template)
+        .replace("{name}", &clean(name))
+        .replace("{date}", &now[..10])
+        .replace("{time}", &now[11..19].replace(':', ""));
+
+    let path = PathBuf::from(formatted.trim());
+    let mut components = path.components();
+    if !path.components().all(|x| matches!(x, Component::Normal(_))) {
+        anyhow::bail!(
+            "Kept .osz path {} is outside of the repository",
+            path.to_string_lossy()
         );
-        let parent = target_path.parent().ok_or(anyhow!("Incorrect file path"))?;
-        std::fs::create_dir_all(parent)
-            .map_err(|x| anyhow!("Failed to make parent directories for file: {}", x))?;
-        let mut file = File::create(&target_path)
-            .map_err(|x| anyhow!("Failed to open target file for writing: {}", x))?;
-        std::io::copy(&mut zip_file, &mut file)
-            .map_err(|x| anyhow!("Failed to write file: {}", x))?;
     }
+    if components.next().is_some_and(|x| x.as_os_str() == "map") {
+        anyhow::bail!("Kept .osz path can't be in the map directory");
+    }
+    if path.as_os_str().is_empty() {
+        anyhow::bail!("Kept .osz path is empty");
+    }
+    Ok(path)
+}
 
-    if config.keep_latest_osz {
-        std::fs::copy(path, repo_path.join(name + ".osz"))
-            .map_err(|x| anyhow!("Failed to copy the latest .osz: {}", x))?;
+/// Turns a formatted branch template into a valid branch name
+fn branch_name(formatted: &str) -> anyhow::Result<String> {
+    let name = formatted
+        .trim()
+        .replace(|c: char| c.is_whitespace() || "~^:?*[\\".contains(c), "-")
+        .replace("..", "-");
+    if !git2::Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+        anyhow::bail!("Invalid branch name: {}", name);
     }
+    Ok(name)
+}
 
-    info!("[{}] Commiting changes...", "i".cyan());
-    git_add_all(&repo);
-    git_commit(&repo);
+/// Checks out `branch`, creating it from the current commit if it doesn't exist yet
+fn git_switch_branch(repo: &Repository, branch: &str) -> anyhow::Result<()> {
+    let refname = format!("refs/heads/{}", branch);
+    let current = repo
+        .head()
+        .ok()
+        .and_then(|x| x.name().map(|x| x.to_string()));
+    if current.as_deref() == Some(refname.as_str()) {
+        return Ok(());
+    }
 
+    if repo.find_branch(branch, git2::BranchType::Local).is_ok() {
+        info!("[{}] Switching to branch {}", "i".cyan(), branch.cyan());
+        let target = repo
+            .find_reference(&refname)
+            .and_then(|x| x.peel_to_commit())
+            .map_err(|x| anyhow!("Failed to find branch {}: {}", branch, x))?;
+        // Staged imports and other maps' edits in a monorepo are kept, or stop the switch
+        repo.checkout_tree(
+            target.as_object(),
+            Some(git2::build::CheckoutBuilder::new().safe()),
+        )
+        .map_err(|x| {
+            anyhow!(
+                "Failed to switch to branch {}, commit or discard the changes in the way: {}",
+                branch,
+                x
+            )
+        })?;
+        repo.set_head(&refname)
+            .map_err(|x| anyhow!("Failed to switch branch: {}", x))?;
+    } else {
+        info!("[{}] Creating branch {}", "i".cyan(), branch.cyan());
+        let commit = repo
+            .head()
+            .and_then(|x| x.peel_to_commit())
+            .map_err(|x| anyhow!("Failed to find the current commit: {}", x))?;
+        repo.branch(branch, &commit, false)
+            .map_err(|x| anyhow!("Failed to create branch: {}", x))?;
+        repo.set_head(&refname)
+            .map_err(|x| anyhow!("Failed to switch branch: {}", x))?;
+    }
     Ok(())
 }
 
-impl Commands {
-    pub fn run(self, config: Arc<Config>) -> anyhow::Result<()> {
-        match self {
-            Self::Import { file, use_repository } => {
-                match std::fs::exists(&file) {
-                    Ok(true) => {}
-                    Ok(false) => anyhow::bail!("File not found!"),
-                    Err(err) => anyhow::bail!("Failed to check if file exists: {}", err),
-                };
-                match import_file(&file, config.clone(), use_repository) {
-                    Ok(_) => info!("Import completed! Don't forget to push!"),
-                    Err(err) => error!("[{}] Import failed! {}", "x".red(), err),
-                };
-            }
+/// Finds the branch imports should go to by default
+///
+/// That's the only local branch, `init.defaultBranch`, `main` or `master`, in that order
+fn git_default_branch(repo: &Repository) -> Option<String> {
+    let branches: Vec<String> = repo
+        .branches(Some(git2::BranchType::Local))
+        .ok()?
+        .filter_map(|x| x.ok())
+        .filter_map(|(branch, _)| branch.name().ok().flatten().map(|x| x.to_string()))
+        .collect();
+    if branches.len() == 1 {
+        return branches.into_iter().next();
+    }
+
+    let configured = repo
+        .config()
+        .and_then(|x| x.get_string("init.defaultBranch"))
+        .ok();
+    configured
+        .into_iter()
+        .chain(["main".to_string(), "master".to_string()])
+        .find(|x| branches.contains(x))
+}
+
+/// Switches a detached HEAD back to the default branch
+fn git_reattach_head(repo: &Repository) -> anyhow::Result<()> {
+    let branch = git_default_branch(repo)
+        .ok_or(anyhow!("HEAD is detached and no default branch was found"))?;
+    warn!(
+        "[{}] HEAD is detached, switching back to {}",
+        "!".yellow(),
+        branch.yellow()
+    );
+    let refname = format!("refs/heads/{}", branch);
+    let target = repo
+        .find_reference(&refname)
+        .and_then(|x| x.peel_to_commit())
+        .map_err(|x| anyhow!("Failed to find branch {}: {}", branch, x))?;
+    // Runs before manual changes are stashed, so they have to survive the switch
+    repo.checkout_tree(
+        target.as_object(),
+        Some(git2::build::CheckoutBuilder::new().safe()),
+    )
+    .map_err(|x| {
+        anyhow!(
+            "Failed to reattach HEAD to {}, commit or discard the changes in the way: {}",
+            branch,
+            x
+        )
+    })?;
+    repo.set_head(&refname)
+        .map_err(|x| anyhow!("Failed to reattach HEAD: {}", x))?;
+    Ok(())
+}
+
+/// Checks out `branch` for an import unless it's checked out already, creating it at
+/// the default branch (or HEAD) if it doesn't exist
+///
+/// Whatever was checked out before is left as it is, with a warning. Uncommitted changes
+/// that switching would overwrite fail the import instead of being lost
+fn git_checkout_main_branch(repo: &Repository, branch: &str) -> anyhow::Result<()> {
+    let refname = format!("refs/heads/{}", branch);
+    let head = match repo.head() {
+        Ok(head) => head,
+        // Nothing is committed yet, the first commit creates the branch
+        Err(_) => {
+            return repo
+                .set_head(&refname)
+                .map_err(|x| anyhow!("Failed to switch to {}: {}", branch, x));
+        }
+    };
+    if head.name() == Some(refname.as_str()) {
+        return Ok(());
+    }
+    let previous = match head.is_branch() {
+        true => format!("branch {}", head.shorthand().unwrap_or_default()),
+        false => {
+            let commit = head.peel_to_commit().ok();
+            let mut options = git2::DescribeOptions::new();
+            options.describe_tags();
+            let described = repo
+                .describe(&options)
+                .and_then(|x| x.format(None))
+                .ok()
+                .or_else(|| commit.map(|x| x.id().to_string()[..7].to_string()))
+                .unwrap_or_default();
+            format!("detached HEAD at {}", described)
+        }
+    };
+
+    let target = match repo.find_branch(branch, git2::BranchType::Local) {
+        Ok(existing) => existing.get().peel_to_commit(),
+        Err(_) => {
+            let start = git_default_branch(repo)
+                .and_then(|x| repo.find_branch(&x, git2::BranchType::Local).ok())
+                .map(|x| x.get().peel_to_commit())
+                .unwrap_or_else(|| head.peel_to_commit())
+                .map_err(|x| anyhow!("Failed to find a commit to create {} at: {}", branch, x))?;
+            info!("[{}] Creating branch {}", "i".cyan(), branch.cyan());
+            repo.branch(branch, &start, false)
+                .map_err(|x| anyhow!("Failed to create branch {}: {}", branch, x))?;
+            Ok(start)
         }
-        Ok(())
     }
+    .map_err(|x| anyhow!("Failed to find the commit of {}: {}", branch, x))?;
+    repo.checkout_tree(
+        target.as_object(),
+        Some(git2::build::CheckoutBuilder::new().safe()),
+    )
+    .map_err(|x| {
+        anyhow!(
+            "Failed to switch from {} to {}, commit or discard the changes in the way: {}",
+            previous,
+            branch,
+            x
+        )
+    })?;
+    repo.set_head(&refname)
+        .map_err(|x| anyhow!("Failed to switch to {}: {}", branch, x))?;
+    warn!(
+        "[{}] Switched from {} to {} for the import, {} was left as it is",
+        "!".yellow(),
+        previous.yellow(),
+        branch.yellow(),
+        previous
+    );
+    Ok(())
 }
 
 // https://github.com/rust-lang/git2-rs/issues/561
-fn git_add_all(repo: &Repository) {
-    let mut index = repo.index().unwrap();
+fn git_add_all(repo: &Repository, pathspec: &str) -> anyhow::Result<()> {
+    let mut index = repo
+        .index()
+        .map_err(|x| anyhow!("Failed to read git index: {}", x))?;
     index
-        .add_all(&["."], git2::IndexAddOption::DEFAULT, None)
-        .unwrap();
-    index.write().unwrap();
+        .add_all([pathspec], git2::IndexAddOption::DEFAULT, None)
+        .map_err(|x| anyhow!("Failed to stage changes: {}", x))?;
+    index
+        .write()
+        .map_err(|x| anyhow!("Failed to write git index: {}", x))?;
+    Ok(())
+}
+
+/// First line of a commit, prefixed with the map's name in monorepos
+fn commit_title(config: &Config, name: &str, title: &str) -> String {
+    match config.monorepo {
+        true => format!("{}: {}", name, title),
+        false => title.to_string(),
+    }
+}
+
+/// Message of an import commit: what changed (`summary`), stats of the imported
+/// `difficulties`, files that failed to extract and the trailers that are enabled
+///
+/// With `--annotate-warnings`, the integrity issues in `files` (asset keys of the
+/// imported map) are marked in the title
+fn import_message(
+    name: &str,
+    config: &Config,
+    difficulties: &[metadata::Difficulty],
+    files: &HashSet<String>,
+    summary: &str,
+    unchanged: bool,
+    skipped: &[String],
+) -> String {
+    let title = match skipped.is_empty() {
+        true => "Map update",
+        false => "Map update (partial import)",
+    };
+    let mut message = commit_title(config, name, title);
+    let missing_audio = metadata::missing_audio(difficulties, files);
+    for difficulty in &missing_audio {
+        warn!(
+            "[{}] {} uses the audio file {}, which isn't in the archive",
+            "!".yellow(),
+            difficulty.version.yellow(),
+            difficulty.audio_filename.as_deref().unwrap_or_default()
+        );
+    }
+    if config.annotate_warnings && !missing_audio.is_empty() {
+        message += " [warning: missing audio]";
+    }
+    let stats = metadata::stats(difficulties);
+    if !stats.is_empty() {
+        message += "\n\n";
+        message += &stats;
+    }
+    if !summary.is_empty() {
+        message += "\n\n";
+        message += summary;
+    }
+    if unchanged {
+        message += "\n\nNo files changed";
+    }
+    if !skipped.is_empty() {
+        message += "\n\nSkipped files that failed to extract:";
+        for name in skipped {
+            message += "\n- ";
+            message += name;
+        }
+    }
+    // Trailers have to be in the last paragraph together
+    let mut trailers = vec![];
+    if config.collab_trailers {
+        trailers.push(collab_trailers(difficulties, &config.collab_email));
+    }
+    if config.record_provenance {
+        trailers.push(provenance_trailers(config));
+    }
+    trailers.retain(|x| !x.is_empty());
+    if !trailers.is_empty() {
+        message += "\n\n";
+        message += &trailers.join("\n");
+    }
+    message
+}
+
+/// Builds Co-authored-by trailers when the map has more than one mapper
+fn collab_trailers(difficulties: &[metadata::Difficulty], email_template: &str) -> String {
+    let creators = metadata::creators(difficulties);
+    if creators.len() < 2 {
+        return String::new();
+    }
+
+    creators
+        .iter()
+        .map(|creator| {
+            let email = email_template.replace("{creator}", &creator.replace(' ', "-"));
+            format!("Co-authored-by: {} <{}>", creator, email)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Records the gitosu version and the options that change what an import commits,
+/// so maps imported by older or differently configured versions can be told apart
+fn provenance_trailers(config: &Config) -> String {
+    let mut options = vec![];
+    let flags = [
+        (config.monorepo, "--monorepo"),
+        (config.raw, "--raw"),
+        (config.group_by_mode, "--group-by-mode"),
+        (config.incremental, "--incremental"),
+        (config.best_effort, "--best-effort"),
+        (config.no_storyboard, "--no-storyboard"),
+        (config.no_video, "--no-video"),
+        (config.prune_orphan_assets, "--prune-orphan-assets"),
+        (config.normalize_names, "--normalize-names"),
+        (config.changelog, "--changelog"),
+        (config.keep_layout, "--keep-layout"),
+    ];
+    options.extend(flags.iter().filter(|x| x.0).map(|x| x.1.to_string()));
+    if config.normalize_eol != LineEnding::Keep
+        && let Some(value) = config.normalize_eol.to_possible_value()
+    {
+        options.push(format!("--normalize-eol {}", value.get_name()));
+    }
+    if let Some(difficulties) = &config.difficulties {
+        options.push(format!("--difficulties {}", difficulties.join(",")));
+    }
+
+    let mut trailers = format!("Gitosu-Version: {}", env!("CARGO_PKG_VERSION"));
+    if !options.is_empty() {
+        trailers += &format!("\nGitosu-Options: {}", options.join(" "));
+    }
+    trailers
+}
+
+/// Max amount of changed files listed in a commit message
+const SUMMARY_MAX_FILES: usize = 30;
+
+/// Lists files changed under `map_prefix` in the index compared to HEAD
+///
+/// Only the map directory is summarized, so the kept .osz (and README)
+/// never make it into the commit message
+fn git_change_summary(repo: &Repository, map_prefix: &Path) -> anyhow::Result<String> {
+    let tree = git_index_tree(repo)?;
+    let parent_tree = repo.head().ok().and_then(|x| x.peel_to_tree().ok());
+    let diff = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)
+        .map_err(|x| anyhow!("Failed to diff staged changes: {}", x))?;
+
+    let mut lines = vec![];
+    for delta in diff.deltas() {
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        let Ok(path) = path.strip_prefix(map_prefix) else {
+            continue;
+        };
+        let status = match delta.status() {
+            git2::Delta::Added => "Added",
+            git2::Delta::Deleted => "Removed",
+            _ => "Modified",
+        };
+        lines.push(format!("{}: {}", status, path.to_string_lossy()));
+    }
+    Ok(format_summary(lines))
+}
+
+/// Joins the lines of a change summary, listing at most [`SUMMARY_MAX_FILES`] files
+fn format_summary(mut lines: Vec<String>) -> String {
+    if lines.len() > SUMMARY_MAX_FILES {
+        let more = lines.len() - SUMMARY_MAX_FILES;
+        lines.truncate(SUMMARY_MAX_FILES);
+        lines.push(format!("...and {} more", more));
+    }
+    lines.join("\n")
 }
 
-fn git_commit(repo: &Repository) {
-    let mut index = repo.index().unwrap();
-    let oid = index.write_tree().unwrap();
-    let signature = repo.signature().unwrap();
-    let parent_commit = repo.head().unwrap().peel_to_commit().unwrap();
-    let tree = repo.find_tree(oid).unwrap();
+/// Difficulties changed by the staged import, e.g. `Hard (modified)`,
+/// named after their version or their file name if it can't be read
+fn git_changed_difficulties(repo: &Repository, map_prefix: &Path, map_path: &Path) -> Vec<String> {
+    let Ok(tree) = repo.index().and_then(|mut x| x.write_tree()) else {
+        return vec![];
+    };
+    let tree = repo.find_tree(tree).ok();
+    let parent_tree = repo.head().ok().and_then(|x| x.peel_to_tree().ok());
+    let Ok(diff) = repo.diff_tree_to_tree(parent_tree.as_ref(), tree.as_ref(), None) else {
+        return vec![];
+    };
+
+    let mut changed = vec![];
+    for delta in diff.deltas() {
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        let Ok(path) = path.strip_prefix(map_prefix) else {
+            continue;
+        };
+        if path.extension().is_none_or(|x| x != "osu") {
+            continue;
+        }
+        let status = match delta.status() {
+            git2::Delta::Added => "added",
+            git2::Delta::Deleted => "removed",
+            _ => "modified",
+        };
+        // Removed difficulties are only in the old tree, the file name has to do
+        let version = std::fs::read(map_path.join(path))
+            .ok()
+            .map(|x| metadata::Difficulty::parse(path.to_path_buf(), &String::from_utf8_lossy(&x)))
+            .map(|x| x.version)
+            .filter(|x| !x.is_empty());
+        let name = version.unwrap_or_else(|| {
+            path.file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+        changed.push(format!("{} ({})", name, status));
+    }
+    changed
+}
+
+/// Whether the missing git identity warning was shown already
+static SIGNATURE_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Signature for commits, falls back to the configured default identity
+/// when git has no identity configured
+fn git_signature(repo: &Repository, config: &Config) -> git2::Signature<'static> {
+    if let Ok(signature) = repo.signature() {
+        return config.commit_tz.apply(signature.to_owned());
+    }
+    if !SIGNATURE_WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "[{}] No git identity configured, committing as {} <{}>",
+            "!".yellow(),
+            config.default_author_name.yellow(),
+            config.default_author_email.yellow()
+        );
+    }
+    let signature = git2::Signature::now(&config.default_author_name, &config.default_author_email)
+        .expect("default author name and email should be valid");
+    config.commit_tz.apply(signature)
+}
+
+/// Signature of the committer, `--committer-name` and `--committer-email`
+/// replace the respective part of the author's signature
+fn git_committer(repo: &Repository, config: &Config) -> git2::Signature<'static> {
+    let author = git_signature(repo, config);
+    if config.committer_name.is_none() && config.committer_email.is_none() {
+        return author;
+    }
+    let name = config
+        .committer_name
+        .as_deref()
+        .unwrap_or(author.name().unwrap_or_default());
+    let email = config
+        .committer_email
+        .as_deref()
+        .unwrap_or(author.email().unwrap_or_default());
+    git2::Signature::new(name, email, &author.when())
+        .map(|x| x.to_owned())
+        .unwrap_or(author)
+}
+
+/// Tree of everything staged in the index of `repo`
+fn git_index_tree(repo: &Repository) -> anyhow::Result<git2::Tree<'_>> {
+    let oid = repo
+        .index()
+        .and_then(|mut x| x.write_tree())
+        .map_err(|x| anyhow!("Failed to write git tree: {}", x))?;
+    repo.find_tree(oid)
+        .map_err(|x| anyhow!("Failed to find git tree: {}", x))
+}
+
+fn git_commit(repo: &Repository, config: &Config, message: &str) -> anyhow::Result<git2::Oid> {
+    let tree = git_index_tree(repo)?;
+    let signature = git_signature(repo, config);
+    let committer = git_committer(repo, config);
+    let parent_commit = repo
+        .head()
+        .and_then(|x| x.peel_to_commit())
+        .map_err(|x| anyhow!("Failed to find the current commit: {}", x))?;
     repo.commit(
         Some("HEAD"),
         &signature,
-        &signature,
-        "Map update",
+        &committer,
+        message,
         &tree,
         &[&parent_commit],
     )
-    .unwrap();
+    .map_err(|x| anyhow!("Failed to commit: {}", x))
 }
 
-fn git_initial_commit(repo: &git2::Repository) {
-    let signature = repo.signature().unwrap();
-    let oid = repo.index().unwrap().write_tree().unwrap();
-    let tree = repo.find_tree(oid).unwrap();
+/// Commits the archive at `path` as `file_name` to `branch`, which shares no history with the map
+fn git_archive_osz(
+    repo: &Repository,
+    config: &Config,
+    branch: &str,
+    path: &Path,
+    file_name: &str,
+    message: &str,
+) -> anyhow::Result<()> {
+    let refname = format!("refs/heads/{}", branch);
+    let parent = match repo.find_reference(&refname) {
+        Ok(reference) => Some(
+            reference
+                .peel_to_commit()
+                .map_err(|x| anyhow!("Failed to read archive branch: {}", x))?,
+        ),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => anyhow::bail!("Failed to read archive branch: {}", err),
+    };
+    let parent_tree = parent.as_ref().map(|x| x.tree()).transpose()?;
+
+    let blob = repo
+        .blob_path(path)
+        .map_err(|x| anyhow!("Failed to store .osz in git: {}", x))?;
+    let mut builder = repo.treebuilder(parent_tree.as_ref())?;
+    builder.insert(file_name, blob, git2::FileMode::Blob.into())?;
+    let tree = repo.find_tree(builder.write()?)?;
+
+    let signature = git_signature(repo, config);
+    let committer = git_committer(repo, config);
+    let parents: Vec<_> = parent.iter().collect();
     repo.commit(
-        Some("HEAD"),
+        Some(&refname),
         &signature,
-        &signature,
-        "New osu! map",
+        &committer,
+        message,
         &tree,
-        &[],
+        &parents,
     )
-    .unwrap();
+    .map_err(|x| anyhow!("Failed to commit to archive branch: {}", x))?;
+    Ok(())
+}
+
+/// Attaches the name, size and git blob hash of the imported archive to `commit` as a note
+fn git_note_osz(
+    repo: &Repository,
+    config: &Config,
+    commit: git2::Oid,
+    osz: &Path,
+) -> anyhow::Result<()> {
+    let size = std::fs::metadata(osz)
+        .map_err(|x| anyhow!("Failed to read .osz size: {}", x))?
+        .len();
+    let hash = git2::Oid::hash_file(git2::ObjectType::Blob, osz)
+        .map_err(|x| anyhow!("Failed to hash .osz: {}", x))?;
+    let note = format!(
+        "Archive: {}\nSize: {} bytes\nBlob: {}\n",
+        osz.file_name().unwrap_or_default().to_string_lossy(),
+        size,
+        hash
+    );
+    let signature = git_signature(repo, config);
+    let committer = git_committer(repo, config);
+    repo.note(&signature, &committer, None, commit, &note, true)
+        .map_err(|x| anyhow!("Failed to add note: {}", x))?;
+    Ok(())
+}
+
+fn git_initial_commit(
+    repo: &git2::Repository,
+    config: &Config,
+    message: &str,
+) -> anyhow::Result<git2::Oid> {
+    let signature = git_signature(repo, config);
+    let committer = git_committer(repo, config);
+    let tree = git_index_tree(repo)?;
+    repo.commit(Some("HEAD"), &signature, &committer, message, &tree, &[])
+        .map_err(|x| anyhow!("Failed to commit: {}", x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exports and repositories directories in a new temporary directory
+    pub(crate) struct Dirs {
+        _root: tempfile::TempDir,
+        pub exports: PathBuf,
+        pub repos: PathBuf,
+    }
+
+    pub(crate) fn dirs() -> Dirs {
+        let root = tempfile::tempdir().unwrap();
+        let exports = root.path().join("exports");
+        let repos = root.path().join("repos");
+        std::fs::create_dir(&exports).unwrap();
+        std::fs::create_dir(&repos).unwrap();
+        Dirs {
+            _root: root,
+            exports,
+            repos,
+        }
+    }
+
+    /// Config for `dirs` with `extra` command line options
+    pub(crate) fn config(dirs: &Dirs, extra: &[&str]) -> Arc<Config> {
+        let mut argv = vec![
+            "gitosu".into(),
+            "-e".into(),
+            dirs.exports.clone().into_os_string(),
+            "-r".into(),
+            dirs.repos.clone().into_os_string(),
+        ];
+        argv.extend(extra.iter().map(Into::into));
+        let args = Args::try_parse_from(argv).unwrap();
+        Arc::new(Config::parse(&args).unwrap())
+    }
+
+    /// Writes an archive with `entries` to `path`, names ending in `/` are directories
+    pub(crate) fn write_osz(path: &Path, entries: &[(&str, &str)]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        for (name, content) in entries {
+            match name.strip_suffix('/') {
+                Some(dir) => writer.add_directory(dir, options).unwrap(),
+                None => {
+                    writer.start_file(*name, options).unwrap();
+                    writer.write_all(content.as_bytes()).unwrap();
+                }
+            }
+        }
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn kept_archives_in_repositories_are_ignored() {
+        let dirs = dirs();
+        let config = config(&dirs, &[]);
+        let repos = dirs.repos.canonicalize().unwrap();
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(&osz, &[("a.osu", "osu file format v14\n")]);
+        assert!(is_export_path(&osz, &config, &repos));
+
+        // What --keep-latest-osz writes, and archives kept elsewhere in the repository
+        import_file(&osz, config.clone(), None, false).unwrap();
+        let repo = repos.join(name);
+        let kept = repo.join(format!("{}.osz", name));
+        std::fs::copy(&osz, &kept).unwrap();
+        assert!(!is_export_path(&kept, &config, &repos));
+        let nested = repo.join("archives").join("old.osz");
+        std::fs::create_dir_all(nested.parent().unwrap()).unwrap();
+        std::fs::copy(&osz, &nested).unwrap();
+        assert!(!is_export_path(&nested, &config, &repos));
+
+        // New exports outside of repositories are still imported, even directly in the
+        // repositories directory
+        let new = dirs.exports.join("Artist - Other (Mapper).osz");
+        std::fs::copy(&osz, &new).unwrap();
+        assert!(is_export_path(&new, &config, &repos));
+        let beside = repos.join("Artist - Other (Mapper).olz");
+        std::fs::copy(&osz, &beside).unwrap();
+        assert!(is_export_path(&beside, &config, &repos));
+        assert!(!is_export_path(
+            &dirs.exports.join("missing.osz"),
+            &config,
+            &repos
+        ));
+        std::fs::write(dirs.exports.join("notes.txt"), "").unwrap();
+        assert!(!is_export_path(
+            &dirs.exports.join("notes.txt"),
+            &config,
+            &repos
+        ));
+    }
+
+    #[test]
+    fn sanitizes_problematic_titles() {
+        let clean = |name: &str| sanitize_name(name, "_");
+        assert_eq!(
+            clean("Re:Zero - Styx Helix (Mapper)"),
+            "Re_Zero - Styx Helix (Mapper)"
+        );
+        assert_eq!(clean("Who? What*? (Mapper)"), "Who_ What__ (Mapper)");
+        assert_eq!(clean("A|B \"quoted\" <tag> (M)"), "A_B _quoted_ _tag_ (M)");
+        assert_eq!(clean("AC/DC - T.N.T. (M)"), "AC_DC - T.N.T. (M)");
+        assert_eq!(clean("back\\slash (M)"), "back_slash (M)");
+        assert_eq!(
+            clean("tab\there\u{7}bell\nnewline"),
+            "tab_here_bell_newline"
+        );
+        // Windows strips trailing dots and spaces
+        assert_eq!(clean("Title..."), "Title");
+        assert_eq!(clean("Title . . "), "Title");
+        assert_eq!(clean(".."), "_");
+        assert_eq!(clean(""), "_");
+        // Device names, with and without extension, in any case
+        assert_eq!(clean("CON"), "_CON");
+        assert_eq!(clean("nul"), "_nul");
+        assert_eq!(clean("Com1.txt"), "_Com1.txt");
+        assert_eq!(clean("CONTROL (M)"), "CONTROL (M)");
+        // Unicode titles are fine as they are
+        assert_eq!(clean("ハルジオン (M)"), "ハルジオン (M)");
+        assert_eq!(sanitize_name("a:b", ""), "ab");
+        assert_eq!(sanitize_name("CON", ""), "_CON");
+    }
+
+    #[test]
+    fn names_lazer_exports() {
+        let name = |file: &str| raw_repo_name(Path::new(file), None, || None);
+        assert_eq!(
+            name("Artist - Title (Mapper).osz"),
+            "Artist - Title (Mapper)"
+        );
+        assert_eq!(
+            name("Artist - Title (Mapper).olz"),
+            "Artist - Title (Mapper)"
+        );
+        // Duplicate numbers osu! adds when exporting the same map again
+        assert_eq!(
+            name("Artist - Title (Mapper) (2).olz"),
+            "Artist - Title (Mapper)"
+        );
+        assert_eq!(
+            name("Artist - Title (Mapper) (13).osz"),
+            "Artist - Title (Mapper)"
+        );
+        // Names that don't follow the scheme fall back to the file name
+        assert_eq!(name("beatmap.olz"), "beatmap");
+        assert_eq!(name("beatmap.osz"), "beatmap");
+        assert_eq!(
+            raw_repo_name(Path::new("beatmap.olz"), None, || Some("A - T (C)".into())),
+            "A - T (C)"
+        );
+    }
+
+    #[test]
+    fn failed_imports_create_no_repository() {
+        let dirs = dirs();
+        let config = config(&dirs, &[]);
+        let name = "Artist - Title (Mapper)";
+        let path = dirs.exports.join(format!("{}.osz", name));
+
+        std::fs::write(&path, b"not a zip archive").unwrap();
+        assert!(import_file(&path, config.clone(), None, false).is_err());
+        assert!(!dirs.repos.join(name).exists());
+
+        write_osz(&path, &[]);
+        let err = import_file(&path, config.clone(), None, false)
+            .err()
+            .unwrap();
+        assert!(err.is::<EmptyArchive>());
+        assert!(!dirs.repos.join(name).exists());
+
+        write_osz(&path, &[("sb/", ""), ("sb/extra/", "")]);
+        let err = import_file(&path, config.clone(), None, false)
+            .err()
+            .unwrap();
+        assert!(err.downcast_ref::<EmptyArchive>().unwrap().only_directories);
+        assert!(!dirs.repos.join(name).exists());
+    }
+
+    /// Commits `files` on top of HEAD on the current branch of `repo`
+    fn commit_files(repo: &Repository, files: &[(&str, &str)]) {
+        let root = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (name, content) in files {
+            std::fs::write(root.join(name), content).unwrap();
+            index.add_path(Path::new(name)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.head().ok().map(|x| x.peel_to_commit().unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            "test",
+            &tree,
+            &parents,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn switching_branches_keeps_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = Repository::init(root.path()).unwrap();
+        commit_files(&repo, &[("a.osu", "1"), ("b.osu", "1")]);
+        let first = repo.head().unwrap().shorthand().unwrap().to_string();
+        git_switch_branch(&repo, "other").unwrap();
+        commit_files(&repo, &[("a.osu", "2")]);
+
+        // Changes to files both branches have in common are carried over
+        std::fs::write(root.path().join("b.osu"), "staged").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("b.osu")).unwrap();
+        index.write().unwrap();
+        git_switch_branch(&repo, &first).unwrap();
+        assert_eq!(repo.head().unwrap().shorthand(), Some(first.as_str()));
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a.osu")).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("b.osu")).unwrap(),
+            "staged"
+        );
+
+        // Changes the switch would overwrite stop it
+        std::fs::write(root.path().join("a.osu"), "edited").unwrap();
+        assert!(git_switch_branch(&repo, "other").is_err());
+        assert_eq!(repo.head().unwrap().shorthand(), Some(first.as_str()));
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a.osu")).unwrap(),
+            "edited"
+        );
+    }
+
+    #[test]
+    fn failed_resets_keep_the_history() {
+        let dirs = dirs();
+        let config = config(&dirs, &["--force"]);
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(&osz, &[("a.osu", "osu file format v14\n")]);
+        import_file(&osz, config.clone(), None, false).unwrap();
+        let repo_path = dirs.repos.join(name);
+        let repo = Repository::open(&repo_path).unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        drop(repo);
+
+        // Archives only fail their checksum once they're extracted
+        let mut writer = zip::ZipWriter::new(File::create(&osz).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("a.osu", options).unwrap();
+        writer.write_all(b"osu file format v14\n").unwrap();
+        writer.finish().unwrap();
+        let bytes = std::fs::read(&osz).unwrap();
+        let start = bytes.windows(3).position(|x| x == b"v14").unwrap();
+        let mut corrupted = bytes.clone();
+        corrupted[start + 2] = b'5';
+        std::fs::write(&osz, corrupted).unwrap();
+
+        assert!(reset::run(&config, &osz, None, false).is_err());
+        let repo = Repository::open(&repo_path).unwrap();
+        assert_eq!(repo.head().unwrap().target(), Some(head));
+        assert!(!dirs.repos.join(format!(".{}.git-reset", name)).exists());
+    }
+
+    #[test]
+    fn resets_reinstall_hooks() {
+        let dirs = dirs();
+        let hooks = dirs.repos.parent().unwrap().join("hooks");
+        std::fs::create_dir(&hooks).unwrap();
+        std::fs::write(hooks.join("post-commit"), "#!/bin/sh\n").unwrap();
+        let config = config(&dirs, &["--force", "--hooks-dir", hooks.to_str().unwrap()]);
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(&osz, &[("a.osu", "osu file format v14\n")]);
+        import_file(&osz, config.clone(), None, false).unwrap();
+        let repo_path = dirs.repos.join(name);
+        std::fs::remove_file(repo_path.join(".git/hooks/post-commit")).unwrap();
+
+        reset::run(&config, &osz, None, false).unwrap();
+        let repo = Repository::open(&repo_path).unwrap();
+        let mut revwalk = repo.revwalk().unwrap();
+        revwalk.push_head().unwrap();
+        assert_eq!(revwalk.count(), 1);
+        assert!(repo_path.join(".git/hooks/post-commit").is_file());
+        assert!(!dirs.repos.join(format!(".{}.git-reset", name)).exists());
+    }
+
+    #[test]
+    fn raw_imports_guard_unmanaged_repositories() {
+        let dirs = dirs();
+        let config = config(&dirs, &["--raw"]);
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(&osz, &[("a.osu", "osu file format v14\n")]);
+
+        // Repositories gitosu didn't create aren't cleared
+        let repo_path = dirs.repos.join(name);
+        std::fs::create_dir(&repo_path).unwrap();
+        let repo = Repository::init(&repo_path).unwrap();
+        commit_files(&repo, &[("notes.txt", "notes")]);
+        assert!(import_file(&osz, config.clone(), None, false).is_err());
+        assert!(repo_path.join("notes.txt").is_file());
+        assert!(!repos::is_raw(&repo_path));
+        std::fs::remove_dir_all(&repo_path).unwrap();
+
+        // Ones it created are marked, imported into again and listed
+        import_file(&osz, config.clone(), None, false).unwrap();
+        assert!(repos::is_raw(&repo_path));
+        write_osz(&osz, &[("b.osu", "osu file format v14\n")]);
+        import_file(&osz, config.clone(), None, false).unwrap();
+        assert!(repo_path.join("b.osu").is_file());
+        assert!(!repo_path.join("a.osu").exists());
+        let found = repos::scan(&config).unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].raw);
+        assert_eq!(found[0].map_path(), repo_path);
+        let repo = Repository::open(&repo_path).unwrap();
+        let difficulties =
+            repos::committed_difficulties(&repo, "HEAD", &found[0].map_prefix(&config)).unwrap();
+        assert_eq!(difficulties.len(), 1);
+    }
+
+    #[test]
+    fn layouts_are_only_kept_when_asked() {
+        let dirs = dirs();
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(&osz, &[("a.osu", "osu file format v14\n")]);
+        let manifest = dirs.repos.join(name).join(manifest::PATH);
+
+        import_file(&osz, config(&dirs, &[]), None, false).unwrap();
+        assert!(!manifest.exists());
+        write_osz(&osz, &[("b.osu", "osu file format v14\n")]);
+        import_file(&osz, config(&dirs, &["--keep-layout"]), None, false).unwrap();
+        assert!(manifest.is_file());
+    }
+
+    #[test]
+    fn reattaching_head_keeps_changes() {
+        let root = tempfile::tempdir().unwrap();
+        let repo = Repository::init(root.path()).unwrap();
+        commit_files(&repo, &[("a.osu", "1"), ("b.osu", "1")]);
+        let old = repo.head().unwrap().target().unwrap();
+        commit_files(&repo, &[("a.osu", "2")]);
+        let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+        repo.set_head_detached(old).unwrap();
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        repo.checkout_head(Some(checkout.force())).unwrap();
+
+        // Changes the switch would overwrite stop it
+        std::fs::write(root.path().join("a.osu"), "edited").unwrap();
+        assert!(git_reattach_head(&repo).is_err());
+        assert!(repo.head_detached().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a.osu")).unwrap(),
+            "edited"
+        );
+
+        // Other changes are carried over
+        std::fs::write(root.path().join("a.osu"), "1").unwrap();
+        std::fs::write(root.path().join("b.osu"), "edited").unwrap();
+        git_reattach_head(&repo).unwrap();
+        assert_eq!(repo.head().unwrap().shorthand(), Some(branch.as_str()));
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("a.osu")).unwrap(),
+            "2"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.path().join("b.osu")).unwrap(),
+            "edited"
+        );
+    }
+
+    #[test]
+    fn normalized_name_collisions_fail_imports() {
+        let dirs = dirs();
+        let config = config(&dirs, &["--normalize-names"]);
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        write_osz(
+            &osz,
+            &[("a.osu", "osu file format v14\n"), ("a.png", "old")],
+        );
+        import_file(&osz, config.clone(), None, false).unwrap();
+
+        write_osz(
+            &osz,
+            &[
+                ("a.osu", "osu file format v14\n"),
+                ("a.png", "new"),
+                ("a.png ", "other"),
+            ],
+        );
+        assert!(import_file(&osz, config.clone(), None, false).is_err());
+        let map = dirs.repos.join(name).join("map");
+        assert_eq!(std::fs::read_to_string(map.join("a.png")).unwrap(), "old");
+    }
+
+    #[test]
+    fn only_ignore_lock_overrides_the_lock() {
+        let dirs = dirs();
+        let _lock = InstanceLock::acquire(&config(&dirs, &[])).unwrap();
+        assert!(InstanceLock::acquire(&config(&dirs, &[])).is_err());
+        assert!(InstanceLock::acquire(&config(&dirs, &["--force"])).is_err());
+        assert!(InstanceLock::acquire(&config(&dirs, &["--ignore-lock"])).is_ok());
+    }
+
+    #[test]
+    fn parses_commit_time_zones() {
+        assert_eq!(CommitTz::parse("local"), Ok(CommitTz::Local));
+        assert_eq!(CommitTz::parse("UTC"), Ok(CommitTz::Fixed(0)));
+        assert_eq!(CommitTz::parse("Z"), Ok(CommitTz::Fixed(0)));
+        assert_eq!(CommitTz::parse("+05:30"), Ok(CommitTz::Fixed(330)));
+        assert_eq!(CommitTz::parse("-0800"), Ok(CommitTz::Fixed(-480)));
+        assert_eq!(CommitTz::parse("+9"), Ok(CommitTz::Fixed(540)));
+        assert_eq!(CommitTz::parse("+14:00"), Ok(CommitTz::Fixed(840)));
+        for value in [
+            "+15:00", "-24", "+02:60", "+123:00", "", "+", "05:30", "+ab:cd", "garbage",
+        ] {
+            assert!(CommitTz::parse(value).is_err(), "{:?} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn rewrites_line_endings() {
+        let text = b"a\r\nb\nc\rd\r\n";
+        assert_eq!(LineEnding::Lf.apply(b"a\r\nb\r\n"), b"a\nb\n");
+        assert_eq!(LineEnding::Crlf.apply(b"a\nb\n"), b"a\r\nb\r\n");
+        // Mixed endings all end up the same, a lone `\r` isn't a line ending
+        assert_eq!(LineEnding::Lf.apply(text), b"a\nb\nc\rd\n");
+        assert_eq!(LineEnding::Crlf.apply(text), b"a\r\nb\r\nc\rd\r\n");
+        assert_eq!(
+            LineEnding::Crlf.apply(&LineEnding::Lf.apply(text)),
+            LineEnding::Crlf.apply(text)
+        );
+
+        assert!(LineEnding::Lf.applies_to(Path::new("dir/a.osu")));
+        assert!(LineEnding::Crlf.applies_to(Path::new("a.OSB")));
+        assert!(!LineEnding::Keep.applies_to(Path::new("a.osu")));
+        for path in ["bg.png", "audio.mp3", "notes.txt", "osu", "a.osu.bak"] {
+            assert!(
+                !LineEnding::Lf.applies_to(Path::new(path)),
+                "{} is rewritten",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn cleans_older_duplicate_exports() {
+        let dirs = dirs();
+        let names = [
+            "Map (A).osz",
+            "Map (A) (1).osz",
+            "Map (A) (2).osz",
+            "Map (A) (3).osz",
+            "Map (A) (4).osz",
+            "Map (B).osz",
+            "Map (B) (1).osz",
+            "Other Map (A) (1).osz",
+        ];
+        for name in names {
+            std::fs::write(dirs.exports.join(name), "").unwrap();
+        }
+        clean_exports(&dirs.exports.join("Map (A) (3).osz"));
+        let mut left: Vec<String> = std::fs::read_dir(&dirs.exports)
+            .unwrap()
+            .map(|x| x.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "Map (A) (4).osz",
+                "Map (B) (1).osz",
+                "Map (B).osz",
+                "Other Map (A) (1).osz"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_import_lists() {
+        let dirs = dirs();
+        let list = dirs.exports.join("list.txt");
+        let absolute = dirs.repos.join("b.osz");
+        let text = format!(
+            "# exports to import\r\n\na.osz\n  \n  # indented comment\nsub/c.osz\tTarget\r\n{}\n",
+            absolute.to_string_lossy()
+        );
+        std::fs::write(&list, text).unwrap();
+        assert_eq!(
+            read_import_list(&list).unwrap(),
+            [
+                (dirs.exports.join("a.osz"), None),
+                (dirs.exports.join("sub/c.osz"), Some("Target".to_string())),
+                (absolute, None),
+            ]
+        );
+
+        std::fs::write(&list, "# nothing\n\n").unwrap();
+        assert!(read_import_list(&list).is_err());
+        std::fs::write(&list, "a.osz\t\n").unwrap();
+        assert!(read_import_list(&list).is_err());
+        std::fs::write(&list, "-\n").unwrap();
+        assert!(read_import_list(&list).is_err());
+    }
+
+    #[test]
+    fn parses_permission_modes() {
+        assert_eq!(parse_mode("664"), Ok(0o664));
+        assert_eq!(parse_mode("0664"), Ok(0o664));
+        assert_eq!(parse_mode("0o2775"), Ok(0o2775));
+        assert_eq!(parse_mode("7777"), Ok(0o7777));
+        for value in [
+            "",
+            "0o",
+            "8",
+            "669",
+            "rw-r--r--",
+            "-644",
+            "10000",
+            "0o17777",
+        ] {
+            assert!(parse_mode(value).is_err(), "{:?} was accepted", value);
+        }
+    }
 }
@@ -0,0 +1,108 @@
+use std::{
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use serde_json::{Value, json};
+use zip::ZipArchive;
+
+use crate::archive;
+
+/// Where the manifest is kept, relative to the directory containing `map/`
+pub const PATH: &str = ".gitosu/archive.json";
+
+/// Layout of the last imported archive, replayed on export so the archive
+/// osu! gets back looks like the one it exported
+#[derive(Debug, Default)]
+pub struct Manifest {
+    /// Zip archive comment
+    pub comment: String,
+    /// Entry paths in the order they appeared in the archive, with forward slashes
+    pub entries: Vec<String>,
+}
+
+impl Manifest {
+    /// Records the entry order and comment of an archive
+    pub fn from_archive<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Self {
+        let comment = String::from_utf8_lossy(zip.comment()).to_string();
+        let mut entries = vec![];
+        for i in 0..zip.len() {
+            let Ok(entry) = zip.by_index_raw(i) else {
+                continue;
+            };
+            if entry.is_dir() {
+                continue;
+            }
+            // Same paths the entries are extracted to
+            if let Some(path) = archive::entry_path(entry.name()) {
+                entries.push(archive_name(&path));
+            }
+        }
+        Self { comment, entries }
+    }
+
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let value: Value = serde_json::from_slice(bytes)
+            .map_err(|x| anyhow!("Failed to parse archive manifest: {}", x))?;
+        Ok(Self {
+            comment: value["comment"].as_str().unwrap_or_default().to_string(),
+            entries: value["entries"]
+                .as_array()
+                .map(|x| {
+                    x.iter()
+                        .filter_map(|x| x.as_str().map(|x| x.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// The manifest of the map in `repo_path`, `None` if it has none
+    pub fn read(repo_path: &Path) -> anyhow::Result<Option<Self>> {
+        match std::fs::read(repo_path.join(PATH)) {
+            Ok(bytes) => Self::parse(&bytes).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => anyhow::bail!("Failed to read archive manifest: {}", err),
+        }
+    }
+
+    pub fn write(&self, repo_path: &Path) -> anyhow::Result<()> {
+        let path = repo_path.join(PATH);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|x| anyhow!("Failed to create {}: {}", parent.to_string_lossy(), x))?;
+        }
+        let value = json!({
+            "comment": self.comment,
+            "entries": self.entries,
+        });
+        let text = serde_json::to_string_pretty(&value)? + "\n";
+        std::fs::write(&path, text).map_err(|x| anyhow!("Failed to write archive manifest: {}", x))
+    }
+
+    /// Sorts `entries` into the recorded order, files the archive didn't have
+    /// come last in their current order
+    pub fn order<T>(&self, entries: &mut [(String, T)]) {
+        let position = |name: &str| {
+            self.entries
+                .iter()
+                .position(|x| x == name)
+                .unwrap_or(usize::MAX)
+        };
+        entries.sort_by_cached_key(|(name, _)| position(name));
+    }
+}
+
+/// Path of a file inside an archive, always with forward slashes
+pub fn archive_name(path: &Path) -> String {
+    path.components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Path of the manifest in a git tree, next to the map directory at `map_prefix`
+pub fn tree_path(map_prefix: &Path) -> PathBuf {
+    map_prefix.parent().unwrap_or(Path::new("")).join(PATH)
+}
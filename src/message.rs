@@ -0,0 +1,209 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use git2::{ObjectType, Oid, Repository, TreeWalkMode, TreeWalkResult};
+use log::info;
+use owo_colors::OwoColorize;
+use zip::ZipArchive;
+
+use crate::{
+    Config, EmptyArchive, archive, excluded_entries, format_summary, import_message, manifest,
+    metadata, normalize, resolve_repo,
+};
+
+/// Prints the message of the commit importing `path` would make, without importing it
+///
+/// The archive is compared with the map committed at HEAD, the same way an import
+/// compares the extracted map with it
+pub fn run(config: &Config, path: &Path, override_repo: Option<String>) -> anyhow::Result<()> {
+    let file = File::open(path).map_err(|x| anyhow!("Failed to open .osz: {}", x))?;
+    let mut zip = ZipArchive::new(file)
+        .map_err(|x| anyhow!("Failed to open .osz as a zip archive: {}", x))?;
+    if !archive::has_files(&mut zip) {
+        return Err(EmptyArchive {
+            only_directories: !zip.is_empty(),
+        }
+        .into());
+    }
+    archive::check_encryption(&mut zip, config.password.as_deref())?;
+    if config.normalize_names {
+        normalize::check_collisions(zip.file_names())?;
+    }
+    let (name, relative) = resolve_repo(path, override_repo, &mut zip, config)?;
+
+    let (repo, map_prefix) = match (config.monorepo, config.raw) {
+        (true, _) => (
+            Repository::open(&config.repos),
+            Path::new(&relative).join("map"),
+        ),
+        (false, true) => (
+            Repository::open(config.repos.join(&relative)),
+            PathBuf::new(),
+        ),
+        (false, false) => (
+            Repository::open(config.repos.join(&relative)),
+            PathBuf::from("map"),
+        ),
+    };
+    let committed = match repo {
+        Ok(repo) => committed_files(&repo, &map_prefix)?,
+        Err(_) => BTreeMap::new(),
+    };
+    if committed.is_empty() {
+        info!(
+            "[{}] {} has no committed map yet, everything would be added",
+            "i".cyan(),
+            relative.cyan()
+        );
+    }
+
+    let excluded = excluded_entries(&mut zip, config)?;
+    let archived = archive_files(&mut zip, &excluded, config)?;
+    let mut lines = vec![];
+    let paths: BTreeSet<&String> = committed.keys().chain(archived.keys()).collect();
+    for path in paths {
+        match (committed.get(path), archived.get(path)) {
+            (None, Some(_)) => lines.push(format!("Added: {}", path)),
+            (Some(_), None) => lines.push(format!("Removed: {}", path)),
+            (Some(old), Some(new)) if old != new => lines.push(format!("Modified: {}", path)),
+            _ => {}
+        }
+    }
+    if lines.is_empty() {
+        println!("The map is already up to date, importing it wouldn't commit anything");
+        return Ok(());
+    }
+
+    let difficulties: Vec<_> =
+        metadata::read_archive_difficulties(&mut zip, config.password.as_deref())?
+            .into_iter()
+            .filter(|x| !excluded.contains(&metadata::asset_key(&x.file.to_string_lossy())))
+            .collect();
+    let files: HashSet<String> = zip
+        .file_names()
+        .map(metadata::asset_key)
+        .filter(|x| !excluded.contains(x))
+        .collect();
+    let summary = format_summary(lines);
+    println!(
+        "{}",
+        import_message(&name, config, &difficulties, &files, &summary, false, &[])
+    );
+    Ok(())
+}
+
+/// Blob ids of the files committed at HEAD under `map_prefix`, by path relative to it
+fn committed_files(repo: &Repository, map_prefix: &Path) -> anyhow::Result<BTreeMap<String, Oid>> {
+    let mut files = BTreeMap::new();
+    let Ok(tree) = repo.head().and_then(|x| x.peel_to_tree()) else {
+        return Ok(files);
+    };
+    let map_tree = match map_prefix.as_os_str().is_empty() {
+        true => tree,
+        false => match tree.get_path(map_prefix) {
+            Ok(entry) => entry
+                .to_object(repo)
+                .and_then(|x| x.peel_to_tree())
+                .map_err(|x| anyhow!("Failed to read the map directory: {}", x))?,
+            Err(_) => return Ok(files),
+        },
+    };
+    map_tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob)
+            && let Some(name) = entry.name()
+        {
+            files.insert(format!("{}{}", dir, name), entry.id());
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(files)
+}
+
+/// Blob ids the files extracted from the archive would have, by path in the map directory
+fn archive_files(
+    zip: &mut ZipArchive<File>,
+    excluded: &HashSet<String>,
+    config: &Config,
+) -> anyhow::Result<BTreeMap<String, Oid>> {
+    let mut files = BTreeMap::new();
+    for i in 0..zip.len() {
+        let mut zip_file = archive::open_entry(zip, i, config.password.as_deref())?;
+        // Same entries an import extracts
+        let Some(path) = archive::entry_path(zip_file.name()) else {
+            continue;
+        };
+        if zip_file.is_dir()
+            || path.components().next() == Some(Component::Normal(".git".as_ref()))
+            || excluded.contains(&metadata::asset_key(&path.to_string_lossy()))
+        {
+            continue;
+        }
+        let path = match config.normalize_names {
+            true => normalize::path(&path),
+            false => path,
+        };
+        let mut bytes = vec![];
+        zip_file
+            .read_to_end(&mut bytes)
+            .map_err(|x| anyhow!("Failed to read {}: {}", path.to_string_lossy(), x))?;
+        // Rewritten like an import rewrites them
+        if config.normalize_names
+            && normalize::has_references(&path)
+            && let Some(rewritten) = normalize::references(&bytes)
+        {
+            bytes = rewritten;
+        }
+        if config.normalize_eol.applies_to(&path) {
+            bytes = config.normalize_eol.apply(&bytes);
+        }
+        files.insert(
+            manifest::archive_name(&path),
+            Oid::hash_object(ObjectType::Blob, &bytes)?,
+        );
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{import_file, tests};
+
+    #[test]
+    fn previews_normalized_names() {
+        let dirs = tests::dirs();
+        let config = tests::config(&dirs, &["--normalize-names"]);
+        let name = "Artist - Title (Mapper)";
+        let osz = dirs.exports.join(format!("{}.osz", name));
+        tests::write_osz(
+            &osz,
+            &[
+                (
+                    "a.osu",
+                    "osu file format v14\n\n[Events]\n0,0,\"bg.jpg \",0,0\n",
+                ),
+                ("bg.jpg ", "image"),
+            ],
+        );
+        import_file(&osz, config.clone(), None, false).unwrap();
+
+        let map = dirs.repos.join(name).join("map");
+        let mut zip = ZipArchive::new(File::open(&osz).unwrap()).unwrap();
+        let files = archive_files(&mut zip, &HashSet::new(), &config).unwrap();
+        assert_eq!(
+            files.keys().collect::<Vec<_>>(),
+            ["a.osu", "bg.jpg"].iter().collect::<Vec<_>>()
+        );
+        for (path, oid) in files {
+            assert_eq!(
+                Oid::hash_file(ObjectType::Blob, map.join(&path)).unwrap(),
+                oid
+            );
+        }
+    }
+}
@@ -0,0 +1,635 @@
+use std::{
+    collections::HashSet,
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use log::warn;
+use owo_colors::OwoColorize;
+
+use crate::archive;
+
+/// Metadata of a single difficulty (.osu file)
+#[derive(Debug, Default, Clone)]
+pub struct Difficulty {
+    /// Path of the .osu file relative to the map directory
+    pub file: PathBuf,
+    pub title: String,
+    pub artist: String,
+    pub creator: String,
+    pub version: String,
+    /// `BeatmapID` of the difficulty, `None` for unsubmitted maps
+    pub beatmap_id: Option<u32>,
+    /// `BeatmapSetID` of the set the difficulty belongs to, `None` for unsubmitted maps
+    pub beatmap_set_id: Option<u32>,
+    pub audio_filename: Option<String>,
+    pub mode: u8,
+    /// `OverallDifficulty` of the difficulty, `None` if it's missing or malformed
+    pub overall_difficulty: Option<f32>,
+    /// Files referenced by the difficulty, as [`asset_key`]s
+    pub assets: HashSet<String>,
+    /// Number of hit objects, `None` without a [HitObjects] section
+    pub objects: Option<usize>,
+    /// Approximate drain time in milliseconds (from the first to the last object, without breaks)
+    pub drain_time: Option<u64>,
+}
+
+impl Difficulty {
+    /// Parses the key-value sections of an .osu file
+    ///
+    /// Parsing is lenient, unknown or malformed lines are skipped
+    pub fn parse(file: PathBuf, text: &str) -> Self {
+        let mut difficulty = Self {
+            file,
+            ..Default::default()
+        };
+
+        // osu!lazer writes a byte order mark
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let mut section = "";
+        let mut breaks = vec![];
+        let mut first_object: Option<f64> = None;
+        let mut last_object: Option<f64> = None;
+        for raw_line in text.lines() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = &line[1..line.len() - 1];
+                if section == "HitObjects" {
+                    difficulty.objects.get_or_insert(0);
+                }
+                continue;
+            }
+            match section {
+                "Events" => {
+                    if let Some(period) = break_period(line) {
+                        breaks.push(period);
+                    }
+                    difficulty.assets.extend(event_assets(raw_line));
+                    continue;
+                }
+                "HitObjects" => {
+                    // x,y,time,type,hitSound,...
+                    let fields: Vec<&str> = line.split(',').collect();
+                    if let Some(time) = fields.get(2).and_then(|x| x.trim().parse::<f64>().ok())
+                        && fields.len() >= 5
+                    {
+                        *difficulty.objects.get_or_insert(0) += 1;
+                        first_object = Some(first_object.map_or(time, |x| x.min(time)));
+                        last_object = Some(last_object.map_or(time, |x| x.max(time)));
+                    }
+                    // Custom per-object sample is the last part of the hitSample field
+                    if let Some(sample) = line
+                        .rsplit(',')
+                        .next()
+                        .and_then(|x| x.split(':').nth(4))
+                        .filter(|x| !x.is_empty())
+                    {
+                        difficulty.assets.insert(asset_key(sample));
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim().to_string();
+            match (section, key.trim()) {
+                ("General", "AudioFilename") => difficulty.audio_filename = Some(value),
+                ("General", "Mode") => difficulty.mode = value.parse().unwrap_or(0),
+                ("Difficulty", "OverallDifficulty") => {
+                    difficulty.overall_difficulty = value.parse().ok()
+                }
+                ("Metadata", "Title") => difficulty.title = value,
+                ("Metadata", "Artist") => difficulty.artist = value,
+                ("Metadata", "Creator") => difficulty.creator = value,
+                ("Metadata", "Version") => difficulty.version = value,
+                ("Metadata", "BeatmapID") => difficulty.beatmap_id = online_id(&value),
+                ("Metadata", "BeatmapSetID") => difficulty.beatmap_set_id = online_id(&value),
+                _ => {}
+            }
+        }
+
+        if let Some(audio) = &difficulty.audio_filename {
+            difficulty.assets.insert(asset_key(audio));
+        }
+        if let (Some(first), Some(last)) = (first_object, last_object) {
+            let breaks: f64 = breaks
+                .iter()
+                .map(|(start, end)| (end.min(last) - start.max(first)).max(0.0))
+                .sum();
+            difficulty.drain_time = Some((last - first - breaks).max(0.0) as u64);
+        }
+        difficulty
+    }
+
+    /// Replaces `{title}`, `{artist}`, `{creator}`, `{version}`, `{mode}`, `{beatmap_id}`
+    /// and `{beatmap_set_id}` in `template`, missing IDs are replaced with nothing
+    pub fn format(&self, template: &str) -> String {
+        let id = |x: Option<u32>| x.map(|x| x.to_string()).unwrap_or_default();
+        template
+            .replace("{title}", &self.title)
+            .replace("{artist}", &self.artist)
+            .replace("{creator}", &self.creator)
+            .replace("{version}", &self.version)
+            .replace("{mode}", self.mode_name())
+            .replace("{beatmap_id}", &id(self.beatmap_id))
+            .replace("{beatmap_set_id}", &id(self.beatmap_set_id))
+    }
+
+    /// Name of the game mode as used by osu!
+    pub fn mode_name(&self) -> &'static str {
+        match self.mode {
+            1 => "taiko",
+            2 => "catch",
+            3 => "mania",
+            _ => "osu",
+        }
+    }
+
+    /// Individual mappers listed in the Creator field
+    ///
+    /// Collab difficulties usually separate mappers with commas or `&`
+    pub fn creators(&self) -> Vec<String> {
+        self.creator
+            .split([',', '&'])
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+}
+
+/// Normalizes a file path for comparing asset references with archive entries
+///
+/// osu! looks files up case-insensitively and accepts both separators
+pub fn asset_key(path: &str) -> String {
+    path.trim()
+        .trim_matches('"')
+        .replace('\\', "/")
+        .trim_start_matches("./")
+        .to_lowercase()
+}
+
+/// Files referenced by an [Events] line (backgrounds, videos, storyboard elements and samples)
+pub fn event_assets(line: &str) -> Vec<String> {
+    // Storyboard commands are indented and never reference files
+    if line.starts_with([' ', '_']) {
+        return vec![];
+    }
+    let fields: Vec<&str> = line.trim().split(',').map(|x| x.trim()).collect();
+    let file = |i: usize| fields.get(i).map(|x| asset_key(x));
+    match fields[0] {
+        // Background and video
+        "0" | "1" | "Video" => file(2).into_iter().collect(),
+        "Sprite" | "4" | "Sample" | "5" => file(3).into_iter().collect(),
+        // Animation frames are named `name0.png`, `name1.png`...
+        "Animation" | "6" => {
+            let Some(path) = file(3) else {
+                return vec![];
+            };
+            let frames: usize = fields.get(6).and_then(|x| x.parse().ok()).unwrap_or(0);
+            let (stem, ext) = match path.rfind('.') {
+                Some(i) => (&path[..i], &path[i..]),
+                None => (path.as_str(), ""),
+            };
+            (0..frames)
+                .map(|i| format!("{}{}{}", stem, i, ext))
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+/// Extensions of the video formats osu! plays
+const VIDEO_EXTENSIONS: [&str; 8] = [
+    ".mp4", ".m4v", ".avi", ".flv", ".mkv", ".webm", ".wmv", ".mpg",
+];
+
+/// Whether the file at `key` (an [`asset_key`]) is a video
+pub fn is_video(key: &str) -> bool {
+    VIDEO_EXTENSIONS.iter().any(|x| key.ends_with(x))
+}
+
+/// Extensions of the images and sounds `--prune-orphan-assets` may leave out, besides videos
+const MEDIA_EXTENSIONS: [&str; 6] = [".jpg", ".jpeg", ".png", ".mp3", ".ogg", ".wav"];
+
+/// Start of the names of files osu! uses without a reference when they are in the map's
+/// root: hitsounds (`soft-hitclap2.wav`) and beatmap skin elements (`hitcircle.png`)
+const IMPLICIT_PREFIXES: [&str; 30] = [
+    "normal-",
+    "soft-",
+    "drum-",
+    "nightcore-",
+    "taiko-",
+    "mania-",
+    "fruit-",
+    "hit",
+    "approachcircle",
+    "reversearrow",
+    "followpoint",
+    "slider",
+    "spinner",
+    "cursor",
+    "default-",
+    "score",
+    "combo",
+    "lighting",
+    "particle",
+    "star",
+    "count",
+    "go",
+    "ready",
+    "section",
+    "play-",
+    "pause-",
+    "fail",
+    "applause",
+    "pippidon",
+    "menu-",
+];
+
+/// Whether the file at `key` (an [`asset_key`]) can be left out when nothing references it,
+/// only media files osu! doesn't look up by name are
+pub fn is_prunable(key: &str) -> bool {
+    let implicit = !key.contains('/') && IMPLICIT_PREFIXES.iter().any(|x| key.starts_with(x));
+    is_media(key) && !implicit
+}
+
+/// Whether the file at `key` (an [`asset_key`]) is an image, sound or video
+pub fn is_media(key: &str) -> bool {
+    is_video(key) || MEDIA_EXTENSIONS.iter().any(|x| key.ends_with(x))
+}
+
+/// Extensions of the text files maps are made of
+const TEXT_EXTENSIONS: [&str; 5] = [".osu", ".osb", ".ini", ".txt", ".md"];
+
+/// Whether the file at `key` (an [`asset_key`]) is one of the text files of a map
+pub fn is_text(key: &str) -> bool {
+    TEXT_EXTENSIONS.iter().any(|x| key.ends_with(x))
+}
+
+/// Parses a `BeatmapID` or `BeatmapSetID`, unsubmitted maps use `0` or `-1`
+fn online_id(value: &str) -> Option<u32> {
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|x| *x > 0)?
+        .try_into()
+        .ok()
+}
+
+/// Start and end time of a break defined by an [Events] line
+fn break_period(line: &str) -> Option<(f64, f64)> {
+    let mut fields = line.split(',').map(|x| x.trim());
+    if !matches!(fields.next()?, "2" | "Break") {
+        return None;
+    }
+    let start = fields.next()?.parse().ok()?;
+    let end = fields.next()?.parse().ok()?;
+    Some((start, end))
+}
+
+/// Files referenced by a storyboard (.osb file)
+pub fn storyboard_assets(text: &str) -> HashSet<String> {
+    let mut section = "";
+    let mut assets = HashSet::new();
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            section = &trimmed[1..trimmed.len() - 1];
+        } else if section == "Events" && !trimmed.is_empty() && !trimmed.starts_with("//") {
+            assets.extend(event_assets(line));
+        }
+    }
+    assets
+}
+
+/// Reads the files referenced by every storyboard in an archive
+pub fn read_archive_storyboard_assets<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+    password: Option<&str>,
+) -> anyhow::Result<HashSet<String>> {
+    let mut assets = HashSet::new();
+    for i in 0..zip.len() {
+        let mut zip_file = archive::open_entry(zip, i, password)?;
+        if !zip_file.name().to_lowercase().ends_with(".osb") {
+            continue;
+        }
+        let mut bytes = vec![];
+        zip_file.read_to_end(&mut bytes)?;
+        assets.extend(storyboard_assets(&String::from_utf8_lossy(&bytes)));
+    }
+    Ok(assets)
+}
+
+/// Which difficulty's metadata names the map and fills its README, for sets whose
+/// difficulties disagree (e.g. a guest difficulty with its own Creator)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataFrom {
+    /// The first difficulty by file name
+    First,
+    /// The difficulty with the highest `OverallDifficulty`, the one with the most
+    /// objects among equally hard ones
+    HighestDiff,
+    /// The difficulty with this version (difficulty name), ignoring case
+    Named(String),
+}
+
+impl MetadataFrom {
+    /// Parses `first`, `highest-diff` or `named:<version>`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "first" => Ok(Self::First),
+            "highest-diff" => Ok(Self::HighestDiff),
+            _ => match value.strip_prefix("named:") {
+                Some(version) if !version.trim().is_empty() => {
+                    Ok(Self::Named(version.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "{} is not first, highest-diff or named:<version>",
+                    value
+                )),
+            },
+        }
+    }
+
+    /// The difficulty to take the metadata from, the first one if no difficulty has
+    /// the requested version. `None` without difficulties
+    pub fn select<'a>(&self, difficulties: &'a [Difficulty]) -> Option<&'a Difficulty> {
+        let selected = match self {
+            Self::First => None,
+            Self::HighestDiff => difficulties.iter().max_by(|a, b| {
+                let od = |x: &Difficulty| x.overall_difficulty.unwrap_or(0.0);
+                od(a)
+                    .total_cmp(&od(b))
+                    .then(a.objects.cmp(&b.objects))
+                    // `max_by` returns the last of equal elements, keep the first
+                    .then(b.file.cmp(&a.file))
+            }),
+            Self::Named(version) => difficulties
+                .iter()
+                .find(|x| x.version.eq_ignore_ascii_case(version)),
+        };
+        selected.or(difficulties.first())
+    }
+}
+
+/// The name osu! gives exports of the map, `Artist - Title (Creator)` without `.osz`,
+/// from the difficulty `from` selects. `None` without a title or creator
+pub fn osz_name(difficulties: &[Difficulty], from: &MetadataFrom) -> Option<String> {
+    let selected = from.select(difficulties)?;
+    if selected.title.is_empty() || selected.creator.is_empty() {
+        return None;
+    }
+    Some(match selected.artist.is_empty() {
+        true => format!("{} ({})", selected.title, selected.creator),
+        false => format!(
+            "{} - {} ({})",
+            selected.artist, selected.title, selected.creator
+        ),
+    })
+}
+
+/// Reads metadata of every difficulty in an archive, sorted by file name
+pub fn read_archive_difficulties<R: Read + Seek>(
+    zip: &mut zip::ZipArchive<R>,
+    password: Option<&str>,
+) -> anyhow::Result<Vec<Difficulty>> {
+    let mut difficulties = vec![];
+    for i in 0..zip.len() {
+        let mut zip_file = archive::open_entry(zip, i, password)?;
+        let Some(path) = archive::entry_path(zip_file.name()) else {
+            continue;
+        };
+        if path.extension().map(|x| x != "osu").unwrap_or(true) {
+            continue;
+        }
+        let mut bytes = vec![];
+        if let Err(err) = zip_file.read_to_end(&mut bytes) {
+            warn!(
+                "[{}] Failed to read {}: {}",
+                "!".yellow(),
+                path.to_string_lossy(),
+                err
+            );
+            continue;
+        }
+        difficulties.push(Difficulty::parse(path, &String::from_utf8_lossy(&bytes)));
+    }
+    difficulties.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(difficulties)
+}
+
+/// Reads metadata of every difficulty in a map directory, sorted by file name
+pub fn read_difficulties(map_path: &Path) -> anyhow::Result<Vec<Difficulty>> {
+    let mut difficulties = vec![];
+    let entries =
+        std::fs::read_dir(map_path).map_err(|x| anyhow!("Failed to read map directory: {}", x))?;
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().map(|x| x != "osu").unwrap_or(true) {
+            continue;
+        }
+        let bytes = match std::fs::read(&path) {
+            Ok(b) => b,
+            Err(err) => {
+                warn!(
+                    "[{}] Failed to read {}: {}",
+                    "!".yellow(),
+                    path.to_string_lossy(),
+                    err
+                );
+                continue;
+            }
+        };
+        let file = path.strip_prefix(map_path).unwrap_or(&path).to_path_buf();
+        difficulties.push(Difficulty::parse(file, &String::from_utf8_lossy(&bytes)));
+    }
+    difficulties.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(difficulties)
+}
+
+/// Asset keys of every file in `map_path` and its subdirectories
+pub fn map_files(map_path: &Path) -> HashSet<String> {
+    fn walk(root: &Path, dir: &Path, files: &mut HashSet<String>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            // Raw repositories have the map in their root
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(x) if x.is_dir() => walk(root, &path, files),
+                Ok(_) => {
+                    let relative = path.strip_prefix(root).unwrap_or(&path);
+                    files.insert(asset_key(&relative.to_string_lossy()));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    let mut files = HashSet::new();
+    walk(map_path, map_path, &mut files);
+    files
+}
+
+/// Difficulties whose `AudioFilename` isn't one of `files` (asset keys)
+pub fn missing_audio<'a>(
+    difficulties: &'a [Difficulty],
+    files: &HashSet<String>,
+) -> Vec<&'a Difficulty> {
+    difficulties
+        .iter()
+        .filter(|x| {
+            x.audio_filename
+                .as_deref()
+                .is_some_and(|audio| !audio.is_empty() && !files.contains(&asset_key(audio)))
+        })
+        .collect()
+}
+
+/// All distinct mappers across the difficulties, in order of appearance
+pub fn creators(difficulties: &[Difficulty]) -> Vec<String> {
+    let mut creators: Vec<String> = vec![];
+    for creator in difficulties.iter().flat_map(|x| x.creators()) {
+        if !creators.iter().any(|x| x.eq_ignore_ascii_case(&creator)) {
+            creators.push(creator);
+        }
+    }
+    creators
+}
+
+/// Aggregate stats for commit messages, e.g. `4 difficulties, 3241 objects total, longest drain 3:42`
+///
+/// Stats that can't be computed for any difficulty are left out
+pub fn stats(difficulties: &[Difficulty]) -> String {
+    if difficulties.is_empty() {
+        return String::new();
+    }
+    let mut parts = vec![match difficulties.len() {
+        1 => "1 difficulty".to_string(),
+        n => format!("{} difficulties", n),
+    }];
+    let counted: Vec<usize> = difficulties.iter().filter_map(|x| x.objects).collect();
+    if !counted.is_empty() {
+        parts.push(match counted.iter().sum::<usize>() {
+            1 => "1 object total".to_string(),
+            n => format!("{} objects total", n),
+        });
+    }
+    if let Some(drain) = difficulties.iter().filter_map(|x| x.drain_time).max() {
+        let seconds = drain / 1000;
+        parts.push(format!(
+            "longest drain {}:{:02}",
+            seconds / 60,
+            seconds % 60
+        ));
+    }
+    parts.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Difficulty as osu!lazer writes it: byte order mark, CRLF and `Key: value` spacing
+    const LAZER_OSU: &str = "\u{feff}osu file format v14\r\n\
+        \r\n\
+        [General]\r\n\
+        AudioFilename: audio.mp3\r\n\
+        Mode: 3\r\n\
+        \r\n\
+        [Metadata]\r\n\
+        Title: Lazer Song\r\n\
+        Artist: Some Artist\r\n\
+        Creator: mapper\r\n\
+        Version: Hard\r\n\
+        BeatmapID: 0\r\n\
+        BeatmapSetID: -1\r\n\
+        \r\n\
+        [Difficulty]\r\n\
+        OverallDifficulty: 7.5\r\n\
+        \r\n\
+        [Events]\r\n\
+        0,0,\"bg.jpg\",0,0\r\n\
+        \r\n\
+        [HitObjects]\r\n\
+        64,192,1000,1,0,0:0:0:0:\r\n\
+        192,192,2000,1,0,0:0:0:0:soft-hitclap9.wav\r\n";
+
+    /// The same difficulty as osu!stable writes it, without spaces after the colons
+    const STABLE_OSU: &str = "osu file format v14\n\
+        [General]\n\
+        AudioFilename:audio.mp3\n\
+        Mode:3\n\
+        [Metadata]\n\
+        Title:Lazer Song\n\
+        Artist:Some Artist\n\
+        Creator:mapper\n\
+        Version:Hard\n\
+        [Difficulty]\n\
+        OverallDifficulty:7.5\n";
+
+    #[test]
+    fn parses_lazer_difficulties() {
+        let difficulty = Difficulty::parse(PathBuf::from("hard.osu"), LAZER_OSU);
+        assert_eq!(difficulty.title, "Lazer Song");
+        assert_eq!(difficulty.artist, "Some Artist");
+        assert_eq!(difficulty.creator, "mapper");
+        assert_eq!(difficulty.version, "Hard");
+        assert_eq!(difficulty.mode_name(), "mania");
+        assert_eq!(difficulty.overall_difficulty, Some(7.5));
+        assert_eq!(difficulty.beatmap_id, None);
+        assert_eq!(difficulty.beatmap_set_id, None);
+        assert_eq!(difficulty.objects, Some(2));
+        assert_eq!(difficulty.drain_time, Some(1000));
+        for asset in ["audio.mp3", "bg.jpg", "soft-hitclap9.wav"] {
+            assert!(difficulty.assets.contains(asset), "{}", asset);
+        }
+        assert_eq!(
+            osz_name(&[difficulty], &MetadataFrom::First).as_deref(),
+            Some("Some Artist - Lazer Song (mapper)")
+        );
+    }
+
+    #[test]
+    fn pluralizes_stats() {
+        let difficulty = |objects: Option<usize>| Difficulty {
+            objects,
+            ..Default::default()
+        };
+        assert_eq!(stats(&[]), "");
+        assert_eq!(
+            stats(&[difficulty(Some(1))]),
+            "1 difficulty, 1 object total"
+        );
+        assert_eq!(
+            stats(&[difficulty(Some(0))]),
+            "1 difficulty, 0 objects total"
+        );
+        assert_eq!(
+            stats(&[difficulty(Some(2)), difficulty(None)]),
+            "2 difficulties, 2 objects total"
+        );
+    }
+
+    #[test]
+    fn spacing_doesnt_change_metadata() {
+        let lazer = Difficulty::parse(PathBuf::from("hard.osu"), LAZER_OSU);
+        let stable = Difficulty::parse(PathBuf::from("hard.osu"), STABLE_OSU);
+        assert_eq!(lazer.title, stable.title);
+        assert_eq!(lazer.artist, stable.artist);
+        assert_eq!(lazer.creator, stable.creator);
+        assert_eq!(lazer.version, stable.version);
+        assert_eq!(lazer.mode, stable.mode);
+        assert_eq!(lazer.audio_filename, stable.audio_filename);
+        assert_eq!(lazer.overall_difficulty, stable.overall_difficulty);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use owo_colors::OwoColorize;
+
+/// Import counters, only exposed when metrics are enabled
+pub static IMPORTS_SUCCEEDED: AtomicU64 = AtomicU64::new(0);
+pub static IMPORTS_FAILED: AtomicU64 = AtomicU64::new(0);
+pub static IMPORTS_SKIPPED_EMPTY: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_PROCESSED: AtomicU64 = AtomicU64::new(0);
+pub static EVENTS_DROPPED: AtomicU64 = AtomicU64::new(0);
+
+pub fn inc(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add(counter: &AtomicU64, value: u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+fn get(counter: &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}
+
+/// Renders all counters in the Prometheus text exposition format
+pub fn render() -> String {
+    let metrics = [
+        (
+            "gitosu_imports_succeeded_total",
+            "Imports completed successfully",
+            &IMPORTS_SUCCEEDED,
+        ),
+        (
+            "gitosu_imports_failed_total",
+            "Imports that failed",
+            &IMPORTS_FAILED,
+        ),
+        (
+            "gitosu_imports_skipped_empty_total",
+            "Imports skipped because the archive was empty",
+            &IMPORTS_SKIPPED_EMPTY,
+        ),
+        (
+            "gitosu_bytes_processed_total",
+            "Bytes extracted from imported archives",
+            &BYTES_PROCESSED,
+        ),
+        (
+            "gitosu_events_dropped_total",
+            "Filesystem events dropped because the event queue was full",
+            &EVENTS_DROPPED,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, help, counter) in metrics {
+        out += &format!(
+            "# HELP {name} {help}\n# TYPE {name} counter\n{name} {}\n",
+            get(counter)
+        );
+    }
+    out
+}
+
+/// Logs a one-line summary of all counters
+pub fn log_summary() {
+    info!(
+        "[{}] Imports: {} succeeded, {} failed, {} skipped (empty), {} bytes processed",
+        "i".cyan(),
+        get(&IMPORTS_SUCCEEDED),
+        get(&IMPORTS_FAILED),
+        get(&IMPORTS_SKIPPED_EMPTY),
+        get(&BYTES_PROCESSED),
+    );
+}
+
+/// Spawns a thread logging a summary every `interval`
+pub fn spawn_summary_logger(interval: Duration) {
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(interval);
+            log_summary();
+        }
+    });
+}
+
+/// Spawns a thread serving the counters over HTTP on `addr`
+pub fn spawn_server(addr: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|x| anyhow::anyhow!("Failed to bind metrics address {}: {}", addr, x))?;
+    info!(
+        "[{}] Serving metrics on {}",
+        "i".cyan(),
+        format!("http://{}/metrics", addr).cyan()
+    );
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(s) => s,
+                Err(err) => {
+                    debug!("metrics connection failed: {}", err);
+                    continue;
+                }
+            };
+            // The request itself doesn't matter, every path returns the metrics
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+
+            let body = render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(err) = stream.write_all(response.as_bytes()) {
+                warn!(
+                    "[{}] Failed to write metrics response: {}",
+                    "!".yellow(),
+                    err
+                );
+            }
+        }
+    });
+
+    Ok(())
+}
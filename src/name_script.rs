@@ -0,0 +1,78 @@
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use serde_json::{Value, json};
+
+use crate::metadata::Difficulty;
+
+/// How long the script may take before it's killed and built-in naming is used
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the `--name-script` `script` for the archive at `path`, returning the first line
+/// it printed as the repository name
+///
+/// The script gets the archive's path, the name gitosu would use and the metadata of
+/// every difficulty as a JSON object on stdin
+pub fn run(
+    script: &Path,
+    path: &Path,
+    default_name: &str,
+    difficulties: &[Difficulty],
+) -> anyhow::Result<String> {
+    let input = json!({
+        "path": path.to_string_lossy(),
+        "file_name": path.file_name().map(|x| x.to_string_lossy()),
+        "default_name": default_name,
+        "difficulties": difficulties.iter().map(|x| json!({
+            "file": x.file.to_string_lossy(),
+            "title": x.title,
+            "artist": x.artist,
+            "creator": x.creator,
+            "version": x.version,
+            "mode": x.mode_name(),
+            "beatmap_id": x.beatmap_id,
+            "beatmap_set_id": x.beatmap_set_id,
+        })).collect::<Vec<Value>>(),
+    });
+
+    let mut child = Command::new(script)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|x| anyhow!("Failed to run {}: {}", script.to_string_lossy(), x))?;
+    // Scripts that exit without reading their input close the pipe, that's fine
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(input.to_string().as_bytes());
+    }
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("Script didn't finish within {}s", TIMEOUT.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    if !status.success() {
+        anyhow::bail!("Script failed ({})", status);
+    }
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout
+            .read_to_string(&mut output)
+            .map_err(|x| anyhow!("Failed to read the script's output: {}", x))?;
+    }
+    match output.lines().next().map(str::trim) {
+        Some(name) if !name.is_empty() => Ok(name.to_string()),
+        _ => anyhow::bail!("Script printed no name"),
+    }
+}
@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::archive;
+
+/// Normalizes a single file or directory name: NFC, without trailing whitespace
+///
+/// Names that would end up empty are kept as they are
+fn name(name: &str) -> String {
+    let normalized: String = name.nfc().collect();
+    match normalized.trim_end() {
+        "" => name.to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// `path` with every component normalized, see [`name`]
+pub fn path(path: &Path) -> PathBuf {
+    path.components()
+        .map(|x| match x {
+            Component::Normal(part) => match part.to_str() {
+                Some(part) => PathBuf::from(name(part)),
+                None => PathBuf::from(part),
+            },
+            other => PathBuf::from(other.as_os_str()),
+        })
+        .collect()
+}
+
+/// Fails if two files of the archive with the entries `names` get the same path once
+/// they're normalized, e.g. `a.png` and `a.png `, as one would overwrite the other
+pub fn check_collisions<'a>(names: impl Iterator<Item = &'a str>) -> anyhow::Result<()> {
+    let mut seen: HashMap<PathBuf, &str> = HashMap::new();
+    for original in names {
+        // Directories are created for the files in them, invalid names are skipped anyway
+        if original.ends_with(['/', '\\']) {
+            continue;
+        }
+        let Some(entry) = archive::entry_path(original) else {
+            continue;
+        };
+        match seen.insert(path(&entry), original) {
+            Some(other) if other != original => anyhow::bail!(
+                "{:?} and {:?} have the same name with --normalize-names",
+                other,
+                original
+            ),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// A file reference from an .osu or .osb file normalized the same way as extracted
+/// paths, keeping its separators and quotes
+fn reference(value: &str) -> String {
+    let (quoted, inner) = match value.strip_prefix('"').and_then(|x| x.strip_suffix('"')) {
+        Some(inner) => (true, inner),
+        None => (false, value),
+    };
+    let mut result = String::new();
+    let mut part = String::new();
+    for c in inner.chars() {
+        if c == '/' || c == '\\' {
+            result += &name(&part);
+            result.push(c);
+            part.clear();
+        } else {
+            part.push(c);
+        }
+    }
+    result += &name(&part);
+    match quoted {
+        true => format!("\"{}\"", result),
+        false => result,
+    }
+}
+
+/// Whether the file at `path` can reference other files of the map
+pub fn has_references(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|x| x.eq_ignore_ascii_case("osu") || x.eq_ignore_ascii_case("osb"))
+}
+
+/// Rewrites the file references in the .osu or .osb file `text` to point at the
+/// normalized paths, `None` if there was nothing to rewrite
+///
+/// Covers the same references as parsing: the audio file, [Events] files and
+/// custom hit object samples. Everything else is kept byte for byte
+pub fn references(text: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(text).ok()?;
+    let mut result = String::with_capacity(text.len());
+    let mut section = "";
+    let mut changed = false;
+    for raw_line in text.split_inclusive('\n') {
+        let content = raw_line.trim_end_matches(['\r', '\n']);
+        let ending = &raw_line[content.len()..];
+        let line = content.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            section = &line[1..line.len() - 1];
+            result += raw_line;
+            continue;
+        }
+        let rewritten = match section {
+            "General" => audio_line(content),
+            "Events" => event_line(content),
+            "HitObjects" => hit_object_line(content),
+            _ => None,
+        };
+        match rewritten {
+            Some(line) if line != content => {
+                changed = true;
+                result += &line;
+                result += ending;
+            }
+            _ => result += raw_line,
+        }
+    }
+    changed.then(|| result.into_bytes())
+}
+
+/// `AudioFilename: audio.mp3`
+fn audio_line(line: &str) -> Option<String> {
+    let (key, value) = line.split_once(':')?;
+    if key.trim() != "AudioFilename" {
+        return None;
+    }
+    let indent = &value[..value.len() - value.trim_start().len()];
+    Some(format!("{}:{}{}", key, indent, reference(value.trim())))
+}
+
+/// `0,0,"bg.jpg",0,0`, `Sprite,Foreground,Centre,"sb/star.png",320,240`...
+fn event_line(line: &str) -> Option<String> {
+    // Storyboard commands are indented and never reference files
+    if line.starts_with([' ', '_']) {
+        return None;
+    }
+    let mut fields: Vec<String> = line.split(',').map(str::to_string).collect();
+    let index = match fields[0].trim() {
+        "0" | "1" | "Video" => 2,
+        "Sprite" | "4" | "Sample" | "5" | "Animation" | "6" => 3,
+        _ => return None,
+    };
+    let field = fields.get_mut(index)?;
+    let trimmed = field.trim();
+    let start = field.find(trimmed).unwrap_or(0);
+    *field = format!(
+        "{}{}{}",
+        &field[..start],
+        reference(trimmed),
+        &field[start + trimmed.len()..]
+    );
+    Some(fields.join(","))
+}
+
+/// `x,y,time,type,hitSound,...,normalSet:additionSet:index:volume:filename`
+fn hit_object_line(line: &str) -> Option<String> {
+    let (objects, sample) = line.rsplit_once(',')?;
+    let mut parts: Vec<&str> = sample.split(':').collect();
+    if parts.len() < 5 || parts[4].is_empty() {
+        return None;
+    }
+    let file = reference(parts[4]);
+    parts[4] = &file;
+    Some(format!("{},{}", objects, parts.join(":")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_collisions() {
+        assert!(check_collisions(["a.png", "sb/a.png", "b.png\u{301}"].into_iter()).is_ok());
+        assert!(check_collisions(["sb/", "sb/a.png"].into_iter()).is_ok());
+        assert!(check_collisions(["a.png", "a.png "].into_iter()).is_err());
+        assert!(check_collisions(["sb /a.png", "sb/a.png"].into_iter()).is_err());
+        // é composed and decomposed
+        assert!(check_collisions(["\u{e9}.png", "e\u{301}.png"].into_iter()).is_err());
+    }
+}
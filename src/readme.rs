@@ -0,0 +1,171 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use log::info;
+use owo_colors::OwoColorize;
+
+use crate::{
+    Config, git_add_all, git_commit,
+    metadata::{self, Difficulty, MetadataFrom},
+    repos::{self, ManagedRepo},
+};
+
+/// README of new repositories without a template repository
+pub const DEFAULT: &str = include_str!("defaultreadme.md");
+
+/// Markers around the generated map info, only what's between them is updated later
+const SECTION_START: &str = "<!-- gitosu:diffs -->";
+const SECTION_END: &str = "<!-- /gitosu:diffs -->";
+
+/// README template of new repositories: README.md of the template repository or the default one,
+/// `None` if the template repository has no README
+pub fn template(config: &Config) -> Option<String> {
+    match &config.template_repo {
+        Some(template) => std::fs::read_to_string(template.join("README.md")).ok(),
+        None => Some(DEFAULT.to_string()),
+    }
+}
+
+/// Fills a README template with the map name and metadata of its difficulties
+///
+/// The metadata is put between markers, so later imports can update it without
+/// touching the rest of the README
+pub fn render(
+    template: &str,
+    name: &str,
+    difficulties: &[Difficulty],
+    from: &MetadataFrom,
+) -> String {
+    template
+        .replace("{map_info}", &section(&map_info(difficulties, from)))
+        .replace("{map_name}", name)
+}
+
+/// Updates the map info between the markers in the README at `path` from the map in
+/// `map_path`, leaving everything else as it is. Nothing happens without markers
+pub fn update(path: &Path, map_path: &Path, from: &MetadataFrom) -> anyhow::Result<()> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => anyhow::bail!("Failed to read README.md: {}", err),
+    };
+    if !has_section(&text) {
+        return Ok(());
+    }
+    let difficulties = metadata::read_difficulties(map_path)?;
+    if let Some(updated) = replace_section(&text, &map_info(&difficulties, from))
+        && updated != text
+    {
+        std::fs::write(path, updated).map_err(|x| anyhow!("Failed to write README.md: {}", x))?;
+    }
+    Ok(())
+}
+
+fn section(info: &str) -> String {
+    match info.is_empty() {
+        true => format!("{}\n{}", SECTION_START, SECTION_END),
+        false => format!("{}\n{}\n{}", SECTION_START, info, SECTION_END),
+    }
+}
+
+fn has_section(text: &str) -> bool {
+    text.find(SECTION_START)
+        .is_some_and(|start| text[start..].contains(SECTION_END))
+}
+
+/// `text` with everything from the start to the end marker replaced with `info`,
+/// `None` without markers
+fn replace_section(text: &str, info: &str) -> Option<String> {
+    let start = text.find(SECTION_START)?;
+    let end = start + text[start..].find(SECTION_END)? + SECTION_END.len();
+    Some(format!(
+        "{}{}{}",
+        &text[..start],
+        section(info),
+        &text[end..]
+    ))
+}
+
+/// Song (of the difficulty `from` selects), mappers and a table of difficulties,
+/// empty without difficulties
+fn map_info(difficulties: &[Difficulty], from: &MetadataFrom) -> String {
+    let Some(selected) = from.select(difficulties) else {
+        return String::new();
+    };
+    let mut lines = vec![];
+    let song = match (selected.artist.is_empty(), selected.title.is_empty()) {
+        (false, false) => format!("{} - {}", selected.artist, selected.title),
+        _ => format!("{}{}", selected.artist, selected.title),
+    };
+    if !song.is_empty() {
+        lines.push(format!("**Song:** {}", song));
+    }
+    let creators = metadata::creators(difficulties);
+    if !creators.is_empty() {
+        lines.push(format!("**Mapped by:** {}", creators.join(", ")));
+    }
+
+    // Two trailing spaces are a markdown line break
+    let mut info = lines.join("  \n");
+    if !info.is_empty() {
+        info += "\n\n";
+    }
+    info += "| Difficulty | Mode |\n| --- | --- |";
+    for difficulty in difficulties {
+        info += &format!("\n| {} | {} |", difficulty.version, difficulty.mode_name());
+    }
+    info
+}
+
+/// Regenerates the README of a map from its committed metadata, committing it if it
+/// changed. Returns whether anything was committed
+///
+/// READMEs with markers only get the section between them updated, unless `full` is set
+pub fn refresh(config: &Config, managed: &ManagedRepo, full: bool) -> anyhow::Result<bool> {
+    // Raw repositories contain nothing but the map
+    if managed.raw {
+        return Ok(false);
+    }
+    let repo = managed.open(config)?;
+    let difficulties = repos::committed_difficulties(&repo, "HEAD", &managed.map_prefix(config))?;
+
+    let path = managed.path.join("README.md");
+    let existing = std::fs::read_to_string(&path).ok();
+    let text = match existing.as_deref() {
+        Some(text) if !full && has_section(text) => {
+            replace_section(text, &map_info(&difficulties, &config.metadata_from))
+                .unwrap_or_default()
+        }
+        _ => {
+            let Some(template) = template(config) else {
+                anyhow::bail!("The template repository has no README.md");
+            };
+            render(
+                &template,
+                &managed.name,
+                &difficulties,
+                &config.metadata_from,
+            )
+        }
+    };
+    if existing.is_some_and(|x| x == text) {
+        return Ok(false);
+    }
+    std::fs::write(&path, text).map_err(|x| anyhow!("Failed to write README.md: {}", x))?;
+
+    let (pathspec, message) = match config.monorepo {
+        true => (
+            Path::new(&managed.subdir(config)).join("README.md"),
+            format!("{}: Refresh README", managed.name),
+        ),
+        false => ("README.md".into(), "Refresh README".to_string()),
+    };
+    git_add_all(&repo, &pathspec.to_string_lossy())?;
+    git_commit(&repo, config, &message)?;
+    info!(
+        "[{}] Refreshed README of {}",
+        "+".green(),
+        managed.name.green()
+    );
+    Ok(true)
+}
@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+    thread::ThreadId,
+};
+
+use log::debug;
+
+/// Repositories locked by a thread in this process, with how many times it locked each
+static LOCKED: Mutex<Option<HashMap<PathBuf, (ThreadId, usize)>>> = Mutex::new(None);
+static RELEASED: Condvar = Condvar::new();
+
+/// Lock on a single git repository within this process, held from clearing the map
+/// until its import is committed so two imports can't interleave in one index
+///
+/// The lock is reentrant, a thread can stage another import into a repository it
+/// already holds (like the watcher does when an export is rewritten before its batch
+/// is committed). Other threads wait until it is released
+pub struct RepoLock {
+    path: PathBuf,
+}
+
+impl RepoLock {
+    /// Locks the repository at `path`, waiting for other threads to release it first
+    pub fn acquire(path: &Path) -> Self {
+        let me = std::thread::current().id();
+        let mut locked = LOCKED.lock().unwrap_or_else(|x| x.into_inner());
+        loop {
+            let repos = locked.get_or_insert_with(HashMap::new);
+            match repos.get_mut(path) {
+                None => {
+                    repos.insert(path.to_path_buf(), (me, 1));
+                    break;
+                }
+                Some((owner, count)) if *owner == me => {
+                    *count += 1;
+                    break;
+                }
+                Some(_) => {
+                    debug!("Waiting for {} to be unlocked", path.to_string_lossy());
+                    locked = RELEASED.wait(locked).unwrap_or_else(|x| x.into_inner());
+                }
+            }
+        }
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let mut locked = LOCKED.lock().unwrap_or_else(|x| x.into_inner());
+        if let Some(repos) = locked.as_mut()
+            && let Some((_, count)) = repos.get_mut(&self.path)
+        {
+            *count -= 1;
+            if *count == 0 {
+                repos.remove(&self.path);
+                RELEASED.notify_all();
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::HashMap,
+    path::{Component, Path},
+    sync::{Arc, mpsc::RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info, trace};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use owo_colors::OwoColorize;
+
+use crate::{
+    Config, event_queue,
+    export::{self, Compression},
+    health, repos,
+};
+
+/// How long a repository has to stay unchanged before it's exported,
+/// so a pull or checkout touching many files results in a single export
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// Watches the repositories directory and exports maps whose `map/` changed into `output`
+pub fn watch_repos(config: Arc<Config>, output: &Path) -> anyhow::Result<()> {
+    let (tx, rx) = event_queue::channel(config.event_queue_size, config.event_overflow);
+    let mut watcher = RecommendedWatcher::new(tx, notify::Config::default())?;
+    watcher.watch(&config.repos, RecursiveMode::Recursive)?;
+    // Events use absolute paths
+    let root = config.repos.canonicalize()?;
+
+    info!(
+        "{} is now monitoring repositories in {}, exporting into {}!",
+        "gitosu".purple(),
+        config.repos.to_string_lossy().purple(),
+        output.to_string_lossy().purple()
+    );
+
+    if let Some(addr) = config.health_addr {
+        health::spawn_server(addr, vec![config.repos.clone(), output.to_path_buf()])?;
+    }
+
+    let mut pending: HashMap<String, Instant> = HashMap::new();
+    loop {
+        health::beat();
+        match rx.recv_timeout(SETTLE_TIME) {
+            Ok(Ok(event)) => {
+                trace!("{:#?}", event);
+                if event.kind.is_access() {
+                    continue;
+                }
+                for path in &event.paths {
+                    if let Some(name) = changed_repository(&root, path) {
+                        pending.insert(name, Instant::now());
+                    }
+                }
+            }
+            Ok(Err(err)) => error!("Error while watching repositories: {}", err),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if crate::shutdown_requested() {
+            break;
+        }
+
+        let now = Instant::now();
+        let settled: Vec<String> = pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= SETTLE_TIME)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in settled {
+            pending.remove(&name);
+            let repo_path = config.repos.join(&name);
+            let map_path = match repos::is_raw(&repo_path) {
+                true => repo_path,
+                false => repo_path.join("map"),
+            };
+            if !map_path.is_dir() {
+                continue;
+            }
+            // Without the mode directory of `--group-by-mode`
+            let file_name = name.rsplit('/').next().unwrap_or(&name);
+            let target = output.join(format!("{}.osz", file_name));
+            match export::export_map(&map_path, &target, Compression::Deflate, None) {
+                Ok(_) => info!("Export of {} completed!", name.green()),
+                Err(err) => error!("[{}] Export of {} failed! {}", "x".red(), name, err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the repository, relative to `repos` with forward slashes, if `path` is inside
+/// its map directory, or anywhere but `.git` in a raw repository
+///
+/// Repositories are directly in `repos`, or in a mode directory with `--group-by-mode`
+fn changed_repository(repos: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(repos).ok()?;
+    let parts = relative
+        .components()
+        .map(|x| match x {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let grouped = parts
+        .first()
+        .is_some_and(|x| repos::MODE_DIRECTORIES.contains(&x.as_str()));
+    let depths = match grouped {
+        true => 1..3,
+        false => 1..2,
+    };
+    for depth in depths {
+        let Some(dir) = parts.get(depth) else {
+            break;
+        };
+        let name = parts[..depth].join("/");
+        if dir == "map" || (dir != ".git" && repos::is_raw(&repos.join(&name))) {
+            debug!("{} changed in {}", relative.to_string_lossy(), name);
+            return Some(name);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_changed_repositories() {
+        let root = tempfile::tempdir().unwrap();
+        let repos = root.path();
+        let changed = |path: &str| changed_repository(repos, &repos.join(path));
+
+        assert_eq!(changed("Map/map/a.osu"), Some("Map".to_string()));
+        assert_eq!(changed("Map/map/sb/star.png"), Some("Map".to_string()));
+        assert_eq!(
+            changed("mania/Map/map/a.osu"),
+            Some("mania/Map".to_string())
+        );
+        // A repository named like a mode directory
+        assert_eq!(changed("osu/map/a.osu"), Some("osu".to_string()));
+        assert_eq!(changed("Map/README.md"), None);
+        assert_eq!(changed("Map/.git/index"), None);
+        assert_eq!(changed("Other/Map/map/a.osu"), None);
+        assert_eq!(changed("mania/Map/README.md"), None);
+
+        let raw = repos.join("taiko").join("Raw");
+        std::fs::create_dir_all(raw.join(".git")).unwrap();
+        std::fs::write(raw.join(".git").join(repos::RAW_MARKER), "").unwrap();
+        assert_eq!(changed("taiko/Raw/a.osu"), Some("taiko/Raw".to_string()));
+        assert_eq!(changed("taiko/Raw/.git/index"), None);
+    }
+}
@@ -0,0 +1,529 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use git2::Repository;
+use log::warn;
+use owo_colors::OwoColorize;
+use serde_json::{Value, json};
+
+use crate::{Config, metadata};
+
+/// A map repository found in the repositories directory
+#[derive(Debug, Clone)]
+pub struct ManagedRepo {
+    pub name: String,
+    /// Directory containing `map/` (the repository itself, or its subdirectory in monorepo mode)
+    pub path: PathBuf,
+    /// Imported with `--raw`, the map is the repository root
+    pub raw: bool,
+}
+
+/// File in the git directory of repositories imported with `--raw`, which have no `map/`
+/// that would tell them apart from any other repository
+pub const RAW_MARKER: &str = "gitosu-raw";
+
+/// Whether the repository at `path` was imported into with `--raw`
+pub fn is_raw(path: &Path) -> bool {
+    path.join(".git").join(RAW_MARKER).is_file()
+}
+
+/// Marks `repo` as a raw repository, see [`is_raw`]
+pub fn mark_raw(repo: &Repository) -> anyhow::Result<()> {
+    let marker = repo.path().join(RAW_MARKER);
+    if !marker.exists() {
+        std::fs::write(&marker, "")
+            .map_err(|x| anyhow!("Failed to mark the repository as raw: {}", x))?;
+    }
+    Ok(())
+}
+
+/// The subtree of `tree` at `prefix`, `tree` itself for an empty prefix (raw repositories)
+pub fn subtree<'a>(
+    repo: &'a Repository,
+    tree: &git2::Tree<'a>,
+    prefix: &Path,
+) -> Result<git2::Tree<'a>, git2::Error> {
+    if prefix.as_os_str().is_empty() {
+        return Ok(tree.clone());
+    }
+    tree.get_path(prefix)
+        .and_then(|x| x.to_object(repo))
+        .and_then(|x| x.peel_to_tree())
+}
+
+impl ManagedRepo {
+    /// The map repository at `path`
+    pub fn from_path(path: &Path) -> anyhow::Result<Self> {
+        let path = path
+            .canonicalize()
+            .map_err(|x| anyhow!("Failed to resolve the repository path: {}", x))?;
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .ok_or(anyhow!("Failed to determine the repository name"))?;
+        let raw = is_raw(&path);
+        Ok(Self { name, path, raw })
+    }
+
+    /// Opens the git repository this map lives in
+    pub fn open(&self, config: &Config) -> anyhow::Result<Repository> {
+        let path = match config.monorepo {
+            true => &config.repos,
+            false => &self.path,
+        };
+        Repository::open(path).map_err(|x| anyhow!("Failed to open repository: {}", x))
+    }
+
+    /// Directory of the map relative to the monorepo root with forward slashes,
+    /// e.g. `mania/<name>` with `--group-by-mode`
+    pub fn subdir(&self, config: &Config) -> String {
+        let canonical = |x: &Path| x.canonicalize().unwrap_or_else(|_| x.to_path_buf());
+        match canonical(&self.path).strip_prefix(canonical(&config.repos)) {
+            Ok(relative) => relative
+                .components()
+                .map(|x| x.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => self.name.clone(),
+        }
+    }
+
+    /// Whether the map has uncommitted changes (in its subdirectory in monorepo mode)
+    pub fn is_dirty(&self, config: &Config, repo: &Repository) -> bool {
+        let mut status_options = git2::StatusOptions::new();
+        status_options.include_untracked(true);
+        // libgit2 doesn't match `.` as a pathspec, so the whole repository is checked without one
+        if config.monorepo {
+            status_options.pathspec(self.subdir(config));
+        }
+        repo.statuses(Some(&mut status_options))
+            .map(|x| !x.is_empty())
+            .unwrap_or(false)
+    }
+
+    /// Path of the map directory relative to the repository root, empty in raw repositories
+    pub fn map_prefix(&self, config: &Config) -> PathBuf {
+        match (config.monorepo, self.raw) {
+            (true, _) => Path::new(&self.subdir(config)).join("map"),
+            (false, true) => PathBuf::new(),
+            (false, false) => PathBuf::from("map"),
+        }
+    }
+
+    /// The map directory
+    pub fn map_path(&self) -> PathBuf {
+        match self.raw {
+            true => self.path.clone(),
+            false => self.path.join("map"),
+        }
+    }
+}
+
+/// Directories repositories are grouped into with `--group-by-mode`
+pub const MODE_DIRECTORIES: [&str; 5] = ["osu", "taiko", "catch", "mania", "mixed"];
+
+/// Finds all map repositories (directories with a `map/` folder, and raw repositories),
+/// sorted by path
+///
+/// Repositories grouped into mode directories are found as well
+pub fn scan(config: &Config) -> anyhow::Result<Vec<ManagedRepo>> {
+    let mut repos = vec![];
+    let entries = std::fs::read_dir(&config.repos)
+        .map_err(|x| anyhow!("Failed to read repositories directory: {}", x))?;
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        let is_mode_dir = path
+            .file_name()
+            .is_some_and(|x| MODE_DIRECTORIES.iter().any(|mode| x == *mode));
+        if is_mode_dir && !path.join("map").is_dir() && path.is_dir() {
+            for entry in std::fs::read_dir(&path)? {
+                paths.push(entry?.path());
+            }
+        } else {
+            paths.push(path);
+        }
+    }
+    for path in paths {
+        let raw = !config.monorepo && is_raw(&path);
+        if !path.join("map").is_dir() && !raw {
+            continue;
+        }
+        if !config.monorepo && !path.join(".git").exists() {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        repos.push(ManagedRepo { name, path, raw });
+    }
+    repos.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(repos)
+}
+
+/// Finds every git repository with a `map/` folder anywhere under the repositories
+/// directory, relative to it with forward slashes and sorted
+///
+/// Hidden directories and symlinks aren't searched, and neither are the repositories found
+pub fn discover(config: &Config) -> Vec<String> {
+    let mut found = vec![];
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(config.repos.join(&relative)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let is_dir = entry.file_type().is_ok_and(|x| x.is_dir());
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            if !is_dir || hidden {
+                continue;
+            }
+            let relative = relative.join(entry.file_name());
+            let path = entry.path();
+            match path.join(".git").exists() && path.join("map").is_dir() {
+                true => found.push(
+                    relative
+                        .components()
+                        .map(|x| x.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                ),
+                false => pending.push(relative),
+            }
+        }
+    }
+    found.sort();
+    found
+}
+
+/// The discovered repository (see [`discover`]) whose map committed at HEAD is the same
+/// as the one `difficulties` belong to: the same beatmap set, or the same artist, title
+/// and creator for maps that were never uploaded
+pub fn find_existing(config: &Config, difficulties: &[metadata::Difficulty]) -> Option<String> {
+    let map = difficulties.first()?;
+    if map.beatmap_set_id.is_none() && (map.title.is_empty() || map.creator.is_empty()) {
+        return None;
+    }
+    let matches: Vec<String> = discover(config)
+        .into_iter()
+        .filter(|relative| {
+            let Ok(repo) = Repository::open(config.repos.join(relative)) else {
+                return false;
+            };
+            let Ok(committed) = committed_difficulties(&repo, "HEAD", Path::new("map")) else {
+                return false;
+            };
+            committed
+                .first()
+                .is_some_and(|x| match (x.beatmap_set_id, map.beatmap_set_id) {
+                    (Some(a), Some(b)) => a == b,
+                    _ => {
+                        (&x.artist, &x.title, &x.creator) == (&map.artist, &map.title, &map.creator)
+                    }
+                })
+        })
+        .collect();
+    if matches.len() > 1 {
+        warn!(
+            "[{}] {} existing repositories have this map ({}), using the first one",
+            "!".yellow(),
+            matches.len(),
+            matches.join(", ")
+        );
+    }
+    matches.into_iter().next()
+}
+
+/// Reads the metadata of every difficulty committed at `rev` in the map directory
+pub fn committed_difficulties(
+    repo: &Repository,
+    rev: &str,
+    map_prefix: &Path,
+) -> anyhow::Result<Vec<metadata::Difficulty>> {
+    let tree = repo
+        .revparse_single(rev)
+        .and_then(|x| x.peel_to_tree())
+        .map_err(|x| anyhow!("Failed to read {}: {}", rev, x))?;
+    let map_tree = subtree(repo, &tree, map_prefix)
+        .map_err(|x| anyhow!("Failed to read the map directory at {}: {}", rev, x))?;
+
+    let mut difficulties = vec![];
+    for entry in map_tree.iter() {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        if !name.ends_with(".osu") {
+            continue;
+        }
+        let Ok(blob) = entry.to_object(repo).and_then(|x| x.peel_to_blob()) else {
+            continue;
+        };
+        difficulties.push(metadata::Difficulty::parse(
+            PathBuf::from(name),
+            &String::from_utf8_lossy(blob.content()),
+        ));
+    }
+    difficulties.sort_by(|a, b| a.file.cmp(&b.file));
+    Ok(difficulties)
+}
+
+/// Summary of a repository shown by `gitosu list`
+pub struct RepoInfo {
+    pub name: String,
+    pub song: String,
+    pub difficulties: usize,
+    pub last_import: Option<SystemTime>,
+    pub dirty: bool,
+    /// Commits not pushed to the upstream branch, `None` without an upstream
+    pub unpushed: Option<usize>,
+}
+
+/// Collects the summary of a repository
+pub fn info(config: &Config, managed: &ManagedRepo) -> anyhow::Result<RepoInfo> {
+    let repo = managed.open(config)?;
+    let map_prefix = managed.map_prefix(config);
+
+    let difficulties = committed_difficulties(&repo, "HEAD", &map_prefix).unwrap_or_default();
+    let song = difficulties
+        .first()
+        .filter(|x| !x.title.is_empty())
+        .map(|x| format!("{} - {}", x.artist, x.title))
+        .unwrap_or_default();
+
+    let head = repo.head().ok();
+    let last_import = head
+        .as_ref()
+        .and_then(|x| x.peel_to_commit().ok())
+        .map(|x| SystemTime::UNIX_EPOCH + Duration::from_secs(x.time().seconds().max(0) as u64));
+
+    let dirty = managed.is_dirty(config, &repo);
+
+    let unpushed = head.as_ref().and_then(|head| {
+        let branch = git2::Branch::wrap(repo.find_reference(head.name()?).ok()?);
+        let upstream = branch.upstream().ok()?;
+        let (ahead, _) = repo
+            .graph_ahead_behind(head.target()?, upstream.get().target()?)
+            .ok()?;
+        Some(ahead)
+    });
+
+    Ok(RepoInfo {
+        // Includes the mode directory with `--group-by-mode`
+        name: managed.subdir(config),
+        song,
+        difficulties: difficulties.len(),
+        last_import,
+        dirty,
+        unpushed,
+    })
+}
+
+/// How reporting commands print their results
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Aligned columns for humans
+    Table,
+    /// An array with an object per row
+    Json,
+    /// A header line and a line per row
+    Csv,
+}
+
+/// Prints all managed repositories in the given format
+pub fn list(config: &Config, format: OutputFormat) -> anyhow::Result<()> {
+    let repos: Vec<_> = scan(config)?
+        .into_iter()
+        .map(|managed| {
+            let info = info(config, &managed);
+            (managed, info)
+        })
+        .collect();
+    match format {
+        // Combines the status columns and colors the header
+        OutputFormat::Table => print_table(config, &repos),
+        format => print_rows(
+            format,
+            &[
+                "name",
+                "song",
+                "difficulties",
+                "last_import",
+                "dirty",
+                "unpushed",
+                "error",
+            ],
+            &list_rows(&repos),
+        ),
+    }
+    Ok(())
+}
+
+fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn print_table(config: &Config, repos: &[(ManagedRepo, anyhow::Result<RepoInfo>)]) {
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "SONG".to_string(),
+        "DIFFS".to_string(),
+        "LAST IMPORT".to_string(),
+        "STATUS".to_string(),
+    ]];
+    for (managed, info) in repos {
+        let info = match info {
+            Ok(i) => i,
+            Err(err) => {
+                rows.push([
+                    managed.name.clone(),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    format!("error: {}", err),
+                ]);
+                continue;
+            }
+        };
+        let mut status = vec![];
+        if info.dirty {
+            status.push("dirty".to_string());
+        }
+        match info.unpushed {
+            Some(0) => {}
+            Some(n) => status.push(format!("{} unpushed", n)),
+            None => status.push("no upstream".to_string()),
+        }
+        if status.is_empty() {
+            status.push("clean".to_string());
+        }
+        rows.push([
+            info.name.clone(),
+            info.song.clone(),
+            info.difficulties.to_string(),
+            info.last_import.map(format_time).unwrap_or_default(),
+            status.join(", "),
+        ]);
+    }
+
+    if rows.len() == 1 {
+        println!(
+            "No repositories found in {}",
+            config.repos.to_string_lossy()
+        );
+        return;
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        match i {
+            0 => println!("{}", line.trim_end().bold()),
+            _ => println!("{}", line.trim_end()),
+        }
+    }
+}
+
+/// Rows of `gitosu list` for [`print_rows`], unreadable repositories only have a name
+/// and an error
+fn list_rows(repos: &[(ManagedRepo, anyhow::Result<RepoInfo>)]) -> Vec<Vec<Value>> {
+    repos
+        .iter()
+        .map(|(managed, info)| match info {
+            Ok(info) => vec![
+                json!(info.name),
+                json!(info.song),
+                json!(info.difficulties),
+                json!(info.last_import.map(format_time)),
+                json!(info.dirty),
+                json!(info.unpushed),
+                Value::Null,
+            ],
+            Err(err) => {
+                let mut row = vec![json!(managed.name)];
+                row.extend(std::iter::repeat_n(Value::Null, 5));
+                row.push(json!(err.to_string()));
+                row
+            }
+        })
+        .collect()
+}
+
+/// Prints the results of a reporting command, a row per result with a value per column:
+/// aligned columns, JSON objects keyed by column or CSV lines
+///
+/// Commands print their own report with [`OutputFormat::Table`], this is for the others
+pub fn print_rows(format: OutputFormat, columns: &[&str], rows: &[Vec<Value>]) {
+    let text = |x: &Value| match x {
+        Value::String(x) => x.clone(),
+        Value::Null => String::new(),
+        x => x.to_string(),
+    };
+    match format {
+        OutputFormat::Table => {
+            let mut cells = vec![columns.iter().map(|x| x.to_uppercase()).collect::<Vec<_>>()];
+            cells.extend(rows.iter().map(|row| row.iter().map(text).collect()));
+            let mut widths = vec![0; columns.len()];
+            for row in &cells {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+            for (i, row) in cells.iter().enumerate() {
+                let line = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                    .collect::<Vec<_>>()
+                    .join("  ");
+                match i {
+                    0 => println!("{}", line.trim_end().bold()),
+                    _ => println!("{}", line.trim_end()),
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let objects: Vec<Value> = rows
+                .iter()
+                .map(|row| {
+                    let object = columns
+                        .iter()
+                        .map(|x| x.to_string())
+                        .zip(row.iter().cloned());
+                    Value::Object(object.collect())
+                })
+                .collect();
+            println!("{}", Value::Array(objects));
+        }
+        OutputFormat::Csv => {
+            println!("{}", columns.join(","));
+            for row in rows {
+                let line: Vec<String> = row.iter().map(|x| csv_escape(&text(x))).collect();
+                println!("{}", line.join(","));
+            }
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains separators, quotes or line breaks
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
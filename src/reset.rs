@@ -0,0 +1,256 @@
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use git2::Repository;
+use log::{info, warn};
+use owo_colors::OwoColorize;
+use zip::ZipArchive;
+
+use crate::{
+    Config, EmptyArchive, archive, commit_title, confirm, git_add_all, git_committer,
+    git_initial_commit, git_signature, init_repo, install_hooks, repos, resolve_repo, stage_import,
+};
+
+/// Where the old git directory is moved with `--backup`, relative to the repository
+pub const BACKUP: &str = ".git.bak";
+
+/// Replaces the history of the repository `path` is imported into with a single commit
+/// of the map in `path`, keeping everything outside of `map/` as it is in the working tree
+///
+/// The old git directory is deleted, or moved to [`BACKUP`] with `backup`, once the new
+/// history is committed, and put back if anything fails before that.
+/// Remotes and `--hooks-dir` hooks are set up again in the new repository
+pub fn run(
+    config: &Config,
+    path: &Path,
+    override_repo: Option<String>,
+    backup: bool,
+) -> anyhow::Result<()> {
+    if config.monorepo {
+        anyhow::bail!("Resetting would discard the history of every map in the monorepo");
+    }
+    if config.raw && backup {
+        anyhow::bail!("Imports into raw repositories would delete the backup");
+    }
+    let file = File::open(path).map_err(|x| anyhow!("Failed to open .osz: {}", x))?;
+    let mut zip = ZipArchive::new(file)
+        .map_err(|x| anyhow!("Failed to open .osz as a zip archive: {}", x))?;
+    if !archive::has_files(&mut zip) {
+        return Err(EmptyArchive {
+            only_directories: !zip.is_empty(),
+        }
+        .into());
+    }
+    archive::check_encryption(&mut zip, config.password.as_deref())?;
+    let (name, relative) = resolve_repo(path, override_repo.clone(), &mut zip, config)?;
+    drop(zip);
+    // Names that follow osu!'s scheme win over the override, see `raw_repo_name`
+    if let Some(repo) = &override_repo
+        && *repo != name
+    {
+        anyhow::bail!(
+            "{} would be imported into {}, not {}",
+            path.to_string_lossy(),
+            relative,
+            repo
+        );
+    }
+    let repo_path = config.repos.join(&relative);
+    let git_dir = repo_path.join(".git");
+    if !git_dir.is_dir() {
+        anyhow::bail!(
+            "{} is not a repository, import the archive instead",
+            repo_path.to_string_lossy()
+        );
+    }
+    let backup_dir = repo_path.join(BACKUP);
+    if backup && backup_dir.exists() {
+        anyhow::bail!(
+            "{} already exists, move it out of the way first",
+            backup_dir.to_string_lossy()
+        );
+    }
+
+    warn!(
+        "[{}] This discards the whole history of {}!",
+        "!".yellow(),
+        relative.yellow()
+    );
+    if !config.force && !confirm("Reset its history?") {
+        anyhow::bail!("Not resetting (use --force to skip this question)");
+    }
+
+    let remotes = remotes(&repo_path);
+    // Kept until the new history is committed, outside of the repository so raw imports
+    // don't clear it
+    let previous = previous_git_dir(&repo_path);
+    if previous.exists() {
+        anyhow::bail!(
+            "{} was left by an interrupted reset, move it back to .git or delete it first",
+            previous.to_string_lossy()
+        );
+    }
+    std::fs::rename(&git_dir, &previous)
+        .map_err(|x| anyhow!("Failed to move .git out of the way: {}", x))?;
+    if let Err(err) = rebuild(
+        config,
+        path,
+        override_repo,
+        &name,
+        &repo_path,
+        &remotes,
+        backup,
+    ) {
+        // Drops the new repository, stray "Working tree before reset" commit included
+        let restored = match std::fs::remove_dir_all(&git_dir) {
+            Err(x) if x.kind() != std::io::ErrorKind::NotFound => Err(x),
+            _ => std::fs::rename(&previous, &git_dir),
+        };
+        if let Err(x) = restored {
+            anyhow::bail!(
+                "{}, and restoring the old history failed, it's in {}: {}",
+                err,
+                previous.to_string_lossy(),
+                x
+            );
+        }
+        return Err(err);
+    }
+    match backup {
+        true => std::fs::rename(&previous, &backup_dir)
+            .map_err(|x| anyhow!("Failed to move the old .git to {}: {}", BACKUP, x))?,
+        false => {
+            if let Err(err) = std::fs::remove_dir_all(&previous) {
+                warn!(
+                    "[{}] Failed to remove the old history in {}: {}",
+                    "!".yellow(),
+                    previous.to_string_lossy(),
+                    err
+                );
+            }
+        }
+    }
+
+    info!(
+        "[{}] Reset the history of {}{}",
+        "+".green(),
+        relative.green(),
+        match backup {
+            true => format!(", the old history is in {}", BACKUP),
+            false => String::new(),
+        }
+    );
+    if !remotes.is_empty() {
+        info!(
+            "[{}] Pushing needs --force, the remote still has the old history",
+            "i".cyan()
+        );
+    }
+    Ok(())
+}
+
+/// Where [`run`] keeps the old git directory of the repository at `repo_path`
+/// until the new history is committed
+fn previous_git_dir(repo_path: &Path) -> PathBuf {
+    let name = repo_path.file_name().unwrap_or_default().to_string_lossy();
+    repo_path.with_file_name(format!(".{}.git-reset", name))
+}
+
+/// Creates a new repository at `repo_path` with `remotes` and commits the map in `path`
+/// in it as the only commit
+fn rebuild(
+    config: &Config,
+    path: &Path,
+    override_repo: Option<String>,
+    name: &str,
+    repo_path: &Path,
+    remotes: &[(String, String)],
+    backup: bool,
+) -> anyhow::Result<()> {
+    let repo =
+        init_repo(repo_path, config).map_err(|x| anyhow!("Failed to init repository: {}", x))?;
+    if config.raw {
+        repos::mark_raw(&repo)?;
+    }
+    if let Some(hooks) = &config.hooks_dir {
+        install_hooks(hooks, &repo).map_err(|x| anyhow!("Failed to install git hooks: {}", x))?;
+    }
+    if backup {
+        let info = repo.path().join("info");
+        std::fs::create_dir_all(&info)
+            .and_then(|_| std::fs::write(info.join("exclude"), format!("/{}/\n", BACKUP)))
+            .map_err(|x| anyhow!("Failed to exclude {}: {}", BACKUP, x))?;
+    }
+    for (remote, url) in remotes {
+        if let Err(err) = repo.remote(remote, url) {
+            warn!(
+                "[{}] Failed to add remote {}: {}",
+                "!".yellow(),
+                remote,
+                err
+            );
+        }
+    }
+
+    // Importing needs a commit to import on top of, it's dropped again below
+    git_add_all(&repo, ".")?;
+    git_initial_commit(&repo, config, "Working tree before reset")?;
+    let staged = stage_import(&path.to_path_buf(), config, override_repo)?;
+    // The import staged the map through its own handle to the repository
+    let tree = repo
+        .index()
+        .and_then(|mut x| {
+            x.read(true)?;
+            x.write_tree()
+        })
+        .and_then(|x| repo.find_tree(x))
+        .map_err(|x| anyhow!("Failed to write tree: {}", x))?;
+    let head = repo
+        .head()
+        .map_err(|x| anyhow!("Failed to read HEAD: {}", x))?;
+    let branch = head
+        .name()
+        .ok_or(anyhow!("Branch name is not valid UTF-8"))?
+        .to_string();
+    let message = format!(
+        "{}\n\nHistory reset from {}",
+        commit_title(config, name, "New osu! map"),
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let commit = repo
+        .commit(
+            None,
+            &git_signature(&repo, config),
+            &git_committer(&repo, config),
+            &message,
+            &tree,
+            &[],
+        )
+        .map_err(|x| anyhow!("Failed to commit: {}", x))?;
+    repo.reference(&branch, commit, true, "gitosu: reset-from")
+        .map_err(|x| anyhow!("Failed to update {}: {}", branch, x))?;
+    drop(staged);
+    Ok(())
+}
+
+/// Names and URLs of the remotes of the repository at `path`,
+/// empty if it's too broken to be opened
+fn remotes(path: &Path) -> Vec<(String, String)> {
+    let Ok(repo) = Repository::open(path) else {
+        return vec![];
+    };
+    let Ok(names) = repo.remotes() else {
+        return vec![];
+    };
+    names
+        .iter()
+        .flatten()
+        .filter_map(|name| {
+            let remote = repo.find_remote(name).ok()?;
+            Some((name.to_string(), remote.url()?.to_string()))
+        })
+        .collect()
+}
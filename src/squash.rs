@@ -0,0 +1,160 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use git2::{Commit, Repository};
+use owo_colors::OwoColorize;
+
+use crate::{Config, repos::ManagedRepo};
+
+/// Commits made on the same day, oldest first
+struct Day<'a> {
+    date: String,
+    commits: Vec<Commit<'a>>,
+}
+
+/// Squashes the commits of the checked out branch that are older than the latest `keep`
+/// (and than `older_than`, if given) into one snapshot commit per day, keeping the
+/// latest commits as they are
+///
+/// The branch is rewritten, the old history stays reachable from a backup ref.
+/// Returns the number of commits removed
+pub fn run(
+    config: &Config,
+    managed: &ManagedRepo,
+    keep: usize,
+    older_than: Option<Duration>,
+) -> anyhow::Result<usize> {
+    if config.monorepo {
+        anyhow::bail!("Squashing would rewrite the history of every map in the monorepo");
+    }
+    let repo = managed.open(config)?;
+    let head = repo
+        .head()
+        .map_err(|x| anyhow!("Failed to read HEAD: {}", x))?;
+    if !head.is_branch() {
+        anyhow::bail!("HEAD is detached, check out the branch to squash");
+    }
+    let branch = head
+        .name()
+        .ok_or(anyhow!("Branch name is not valid UTF-8"))?
+        .to_string();
+
+    // Newest first, following the only parent of every commit
+    let mut history = vec![];
+    let mut next = Some(head.peel_to_commit()?);
+    while let Some(commit) = next {
+        if commit.parent_count() > 1 {
+            anyhow::bail!("History contains merges, squashing only works on linear history");
+        }
+        next = commit.parents().next();
+        history.push(commit);
+    }
+
+    let cutoff = older_than
+        .map(|x| {
+            SystemTime::now()
+                .checked_sub(x)
+                .ok_or(anyhow!("--older-than is too large"))
+        })
+        .transpose()?;
+    let squashed = history
+        .iter()
+        .enumerate()
+        .position(|(i, commit)| {
+            i >= keep && cutoff.is_none_or(|cutoff| commit_time(commit) < cutoff)
+        })
+        .unwrap_or(history.len());
+    let (kept, old) = history.split_at(squashed);
+    let days = group_by_day(old);
+    if days.iter().all(|x| x.commits.len() < 2) {
+        return Ok(0);
+    }
+
+    // Snapshots get the tree, author and committer of the last commit of their day,
+    // so squashing the same history twice gives the same commits
+    let mut parent: Option<Commit> = None;
+    for day in &days {
+        let last = day.commits.last().expect("days have at least one commit");
+        let message = match day.commits.len() {
+            1 => last.message_raw().unwrap_or_default().to_string(),
+            n => format!("Map snapshot {}\n\nSquashed {} commits", day.date, n),
+        };
+        parent = Some(recommit(&repo, last, &message, parent.as_ref())?);
+    }
+    for commit in kept.iter().rev() {
+        let message = commit.message_raw().unwrap_or_default().to_string();
+        parent = Some(recommit(&repo, commit, &message, parent.as_ref())?);
+    }
+    let new_head = parent.expect("history is not empty").id();
+
+    let old_head = history[0].id();
+    let backup = format!(
+        "refs/gitosu/backup/{}-{}",
+        branch.trim_start_matches("refs/heads/"),
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    );
+    repo.reference(&backup, old_head, false, "gitosu: backup before squashing")
+        .map_err(|x| anyhow!("Failed to create backup ref: {}", x))?;
+    repo.reference(&branch, new_head, true, "gitosu: squash")
+        .map_err(|x| anyhow!("Failed to update {}: {}", branch, x))?;
+    println!(
+        "[{}] {}: squashed {} commits into {}, backup at {}",
+        "+".green(),
+        managed.name,
+        old.len(),
+        days.len(),
+        backup.cyan()
+    );
+    Ok(old.len() - days.len())
+}
+
+fn commit_time(commit: &Commit) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64)
+}
+
+/// Groups commits (newest first) by the day they were committed on in the committer's
+/// time zone, oldest day first
+fn group_by_day<'a>(commits: &[Commit<'a>]) -> Vec<Day<'a>> {
+    let mut days: Vec<Day> = vec![];
+    for commit in commits.iter().rev() {
+        let time = commit.time();
+        let local = (time.seconds() + time.offset_minutes() as i64 * 60).max(0) as u64;
+        let timestamp =
+            humantime::format_rfc3339_seconds(SystemTime::UNIX_EPOCH + Duration::from_secs(local))
+                .to_string();
+        // `YYYY-MM-DD` of `YYYY-MM-DDTHH:MM:SSZ`
+        let date = timestamp[..10].to_string();
+        match days.last_mut() {
+            Some(day) if day.date == date => day.commits.push(commit.clone()),
+            _ => days.push(Day {
+                date,
+                commits: vec![commit.clone()],
+            }),
+        }
+    }
+    days
+}
+
+/// Commits the tree of `commit` again on top of `parent`, with its author and committer
+fn recommit<'a>(
+    repo: &'a Repository,
+    commit: &Commit,
+    message: &str,
+    parent: Option<&Commit<'a>>,
+) -> anyhow::Result<Commit<'a>> {
+    let parents: Vec<&Commit> = parent.into_iter().collect();
+    let id = repo
+        .commit(
+            None,
+            &commit.author(),
+            &commit.committer(),
+            message,
+            &commit.tree()?,
+            &parents,
+        )
+        .map_err(|x| anyhow!("Failed to commit: {}", x))?;
+    Ok(repo.find_commit(id)?)
+}
@@ -0,0 +1,39 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use git2::{ObjectType, Oid};
+
+/// Where the store is kept, relative to the repositories directory
+pub const DIRECTORY: &str = ".gitosu/store";
+
+/// Replaces the extracted file at `path` with a hard link to the stored file with the
+/// same contents, storing it first if no map had it yet
+///
+/// Files are stored by their git blob id, so identical assets of different maps
+/// take up disk space once. Importing the same file in parallel is fine: only one
+/// of the links into the store succeeds and the others link to it instead
+pub fn link(store: &Path, path: &Path) -> anyhow::Result<()> {
+    let bytes = std::fs::read(path).map_err(|x| anyhow!("Failed to read file: {}", x))?;
+    let id = Oid::hash_object(ObjectType::Blob, &bytes)?.to_string();
+    let stored = store.join(&id[..2]).join(&id);
+    if let Some(parent) = stored.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|x| anyhow!("Failed to create {}: {}", parent.to_string_lossy(), x))?;
+    }
+    match std::fs::hard_link(path, &stored) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+            // Linked under a temporary name first, so `path` never goes missing
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let temp = path.with_file_name(format!(".{}.gitosu-link", name));
+            let _ = std::fs::remove_file(&temp);
+            std::fs::hard_link(&stored, &temp)
+                .and_then(|_| std::fs::rename(&temp, path))
+                .map_err(|x| {
+                    let _ = std::fs::remove_file(&temp);
+                    anyhow!("Failed to link stored file: {}", x)
+                })
+        }
+        Err(err) => anyhow::bail!("Failed to store file: {}", err),
+    }
+}